@@ -0,0 +1,244 @@
+// command: push Constant 7
+@7
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: push Constant 8
+@8
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: arithmetic
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@SP      // Load spa into A
+A=M-1    // Load *spa-1 into A
+M=M+D
+// end command: arithmetic
+
+// command: push Constant 3
+@3
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: arithmetic
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@SP      // Load spa into A
+A=M-1    // Load *spa-1 into A
+M=M-D
+// end command: arithmetic
+
+// command: arithmetic
+@SP      // Load spa into A
+A=M-1    // Load *spa-1 into A
+M=-M
+// end command: arithmetic
+
+// command: push Constant 5
+@5
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: push Constant 5
+@5
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: arithmetic
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@SP      // Load spa into A
+A=M-1    // load *spa-1 into A
+D=M-D    // perform comparison between D and *(*spa-1)
+M=-1     // load true into *(*spa-1)
+@__VM_IMPL_LABEL_0
+D;JEQ    // skip setting value to false if condition is true
+@SP      // load spa into A
+A=M-1    // load *spa-1 into A
+M=0      // load false into *(*spa-1)
+(__VM_IMPL_LABEL_0)
+// end command: arithmetic
+
+// command: push Constant 6
+@6
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: push Constant 2
+@2
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: arithmetic
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@SP      // Load spa into A
+A=M-1    // load *spa-1 into A
+D=M-D    // perform comparison between D and *(*spa-1)
+M=-1     // load true into *(*spa-1)
+@__VM_IMPL_LABEL_1
+D;JGT    // skip setting value to false if condition is true
+@SP      // load spa into A
+A=M-1    // load *spa-1 into A
+M=0      // load false into *(*spa-1)
+(__VM_IMPL_LABEL_1)
+// end command: arithmetic
+
+// command: push Constant 1
+@1
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: push Constant 9
+@9
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: arithmetic
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@SP      // Load spa into A
+A=M-1    // load *spa-1 into A
+D=M-D    // perform comparison between D and *(*spa-1)
+M=-1     // load true into *(*spa-1)
+@__VM_IMPL_LABEL_2
+D;JLT    // skip setting value to false if condition is true
+@SP      // load spa into A
+A=M-1    // load *spa-1 into A
+M=0      // load false into *(*spa-1)
+(__VM_IMPL_LABEL_2)
+// end command: arithmetic
+
+// command: arithmetic
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@SP      // Load spa into A
+A=M-1    // Load *spa-1 into A
+M=M&D
+// end command: arithmetic
+
+// command: arithmetic
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@SP      // Load spa into A
+A=M-1    // Load *spa-1 into A
+M=M|D
+// end command: arithmetic
+
+// command: arithmetic
+@SP      // Load spa into A
+A=M-1    // Load *spa-1 into A
+M=!M
+// end command: arithmetic
+
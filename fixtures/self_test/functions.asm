@@ -0,0 +1,511 @@
+// Bootstrap
+@256
+D=A
+@SP
+M=D
+// command: call Sys.init 0
+// push return address onto stack.
+@__VM_IMPL_LABEL_0
+// action: push
+D=A
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// push old LCL onto stack
+@LCL
+// action: push
+D=M
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// push old ARG onto stack
+@ARG
+// action: push
+D=M
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// push old THIS onto stack
+@THIS
+// action: push
+D=M
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// push old THAT onto stack
+@THAT
+// action: push
+D=M
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// create new ARG pointer
+@0 
+D=A      // load numargs into D
+@5
+D=D+A    // add five to compensate for additional pushed values.
+@SP      // load spa into A
+D=M-D    // load *spa - (numargs + 5) into D
+@ARG     // load argptr into A
+M=D      // load *spa - (numargs + 5) into *argptr
+// create new LCL pointer
+@SP
+D=M
+@LCL
+M=D
+// jump to function
+@Sys.init
+0;JEQ
+(__VM_IMPL_LABEL_0)
+// end command: call {0} {1}
+
+(Sys.init)
+// command: function 0
+// end command: function 0
+
+// command: push Constant 4
+@4
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: call Main.sum 1
+// push return address onto stack.
+@__VM_IMPL_LABEL_1
+// action: push
+D=A
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// push old LCL onto stack
+@LCL
+// action: push
+D=M
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// push old ARG onto stack
+@ARG
+// action: push
+D=M
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// push old THIS onto stack
+@THIS
+// action: push
+D=M
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// push old THAT onto stack
+@THAT
+// action: push
+D=M
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// create new ARG pointer
+@1 
+D=A      // load numargs into D
+@5
+D=D+A    // add five to compensate for additional pushed values.
+@SP      // load spa into A
+D=M-D    // load *spa - (numargs + 5) into D
+@ARG     // load argptr into A
+M=D      // load *spa - (numargs + 5) into *argptr
+// create new LCL pointer
+@SP
+D=M
+@LCL
+M=D
+// jump to function
+@Main.sum
+0;JEQ
+(__VM_IMPL_LABEL_1)
+// end command: call {0} {1}
+
+// command: pop Temp 0
+// action: pop
+@SP
+AM=M-1
+D=M
+@5
+M=D
+(HALT)
+@HALT
+0;JEQ
+(Main.sum)
+// command: function 1
+// push local #0
+D=0
+// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: function 1
+
+(LOOP)
+// command: push Argument 0
+@ARG
+D=M
+@0
+A=D+A
+D=M
+// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: push Constant 0
+@0
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: arithmetic
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@SP      // Load spa into A
+A=M-1    // load *spa-1 into A
+D=M-D    // perform comparison between D and *(*spa-1)
+M=-1     // load true into *(*spa-1)
+@__VM_IMPL_LABEL_2
+D;JEQ    // skip setting value to false if condition is true
+@SP      // load spa into A
+A=M-1    // load *spa-1 into A
+M=0      // load false into *(*spa-1)
+(__VM_IMPL_LABEL_2)
+// end command: arithmetic
+
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@END
+D;JNE
+// command: push Local 0
+@LCL
+D=M
+@0
+A=D+A
+D=M
+// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: push Argument 0
+@ARG
+D=M
+@0
+A=D+A
+D=M
+// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: arithmetic
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@SP      // Load spa into A
+A=M-1    // Load *spa-1 into A
+M=M+D
+// end command: arithmetic
+
+// command: pop Local 0
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@R13
+M=D
+@LCL
+D=M
+@0
+D=D+A
+@R14
+M=D
+@R13
+D=M
+@R14
+A=M
+M=D
+// command: push Argument 0
+@ARG
+D=M
+@0
+A=D+A
+D=M
+// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: push Constant 1
+@1
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: arithmetic
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@SP      // Load spa into A
+A=M-1    // Load *spa-1 into A
+M=M-D
+// end command: arithmetic
+
+// command: pop Argument 0
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@R13
+M=D
+@ARG
+D=M
+@0
+D=D+A
+@R14
+M=D
+@R13
+D=M
+@R14
+A=M
+M=D
+@LOOP
+0;JEQ
+(END)
+// command: push Local 0
+@LCL
+D=M
+@0
+A=D+A
+D=M
+// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: return (1 locals)
+// pop return value
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+// store in R14
+@R14
+M=D
+// deallocate locals
+@LCL
+D=M      // load *localptr into D
+@SP
+M=D      // load D (==*localptr) into *stackptr
+// store ARG value in R15
+@ARG
+D=M
+@R15
+M=D
+// restore old THAT value
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@THAT
+M=D
+// restore old THIS value
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@THIS
+M=D
+// restore old ARG value
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@ARG
+M=D
+// restore old LCL value
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@LCL
+M=D
+// store return address in R13
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@R13
+M=D
+// reset stack pointer from R15 and push return value
+@R15
+D=M
+@SP
+M=D
+@R14
+// action: push
+D=M
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// jump to return address
+@R13
+A=M
+0;JEQ
+// end command: return
+
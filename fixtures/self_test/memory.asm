@@ -0,0 +1,360 @@
+// command: push Constant 3030
+@3030
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: pop Pointer 0
+// action: pop
+@SP
+AM=M-1
+D=M
+@3
+M=D
+// command: push Constant 3040
+@3040
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: pop Pointer 1
+// action: pop
+@SP
+AM=M-1
+D=M
+@4
+M=D
+// command: push Constant 10
+@10
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: pop Local 0
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@R13
+M=D
+@LCL
+D=M
+@0
+D=D+A
+@R14
+M=D
+@R13
+D=M
+@R14
+A=M
+M=D
+// command: push Constant 21
+@21
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: pop Argument 1
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@R13
+M=D
+@ARG
+D=M
+@1
+D=D+A
+@R14
+M=D
+@R13
+D=M
+@R14
+A=M
+M=D
+// command: push Constant 32
+@32
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: pop This 2
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@R13
+M=D
+@THIS
+D=M
+@2
+D=D+A
+@R14
+M=D
+@R13
+D=M
+@R14
+A=M
+M=D
+// command: push Constant 46
+@46
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: pop That 6
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@R13
+M=D
+@THAT
+D=M
+@6
+D=D+A
+@R14
+M=D
+@R13
+D=M
+@R14
+A=M
+M=D
+// command: push Constant 8
+@8
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: pop Temp 6
+// action: pop
+@SP
+AM=M-1
+D=M
+@11
+M=D
+// command: push Constant 9
+@9
+D=A// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: pop Static 3
+// action: pop
+@SP
+AM=M-1
+D=M
+@19
+M=D
+// command: push Local 0
+@LCL
+D=M
+@0
+A=D+A
+D=M
+// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: push Argument 1
+@ARG
+D=M
+@1
+A=D+A
+D=M
+// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: arithmetic
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@SP      // Load spa into A
+A=M-1    // Load *spa-1 into A
+M=M+D
+// end command: arithmetic
+
+// command: push This 2
+@THIS
+D=M
+@2
+A=D+A
+D=M
+// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: push That 6
+@THAT
+D=M
+@6
+A=D+A
+D=M
+// action: push
+@SP      // load stack pointer address into A
+A=M      // load *spa into A.
+M=D      // load D into **spa
+D=A+1    // load *(*spa + 1) into D
+@SP      // load spa into A
+M=D      // load D (==*(*spa + 1)) into *spa
+// end command: push
+
+// command: arithmetic
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@SP      // Load spa into A
+A=M-1    // Load *spa-1 into A
+M=M-D
+// end command: arithmetic
+
+// command: push Temp 6
+@11
+D=M
+// action: push
+@SP
+M=M+1
+A=M-1
+M=D
+// end command: push
+
+// command: push Static 3
+@19
+D=M
+// action: push
+@SP
+M=M+1
+A=M-1
+M=D
+// end command: push
+
+// command: arithmetic
+// action: pop
+@SP      // load stack pointer address into A
+A=M-1    // load *spa-1 into A
+D=M      // load *(*spa-1) into D
+@R13     // load r13addr into A
+M=D      // load *(*spa-1) into *r13addr
+@SP      // load spa into A
+M=M-1    // load *spa-1 into *spa
+@R13     // load r13addr into A
+D=M      // Copy *r13addr (==**spa) into D
+@SP      // Load spa into A
+A=M-1    // Load *spa-1 into A
+M=M+D
+// end command: arithmetic
+
+// command: push Pointer 0
+@3
+D=M
+// action: push
+@SP
+M=M+1
+A=M-1
+M=D
+// end command: push
+
+// command: push Pointer 1
+@4
+D=M
+// action: push
+@SP
+M=M+1
+A=M-1
+M=D
+// end command: push
+
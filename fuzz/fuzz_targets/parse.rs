@@ -0,0 +1,32 @@
+//! Feeds arbitrary bytes to the parser, which should always return an error instead of panicking.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use translator::{options::Options, parse, vm_program::VmProgram};
+
+fuzz_target!(|data: &[u8]| {
+    let mut lenient = Options::default();
+    lenient.allow_unknown_command = true;
+    lenient.comment_char = Some(';');
+    for options in &[Options::default(), lenient] {
+        let source = match parse::decode_source(data.to_vec(), "Fuzz.vm", options) {
+            Ok(source) => source,
+            Err(_) => continue,
+        };
+        let mut program = VmProgram::new();
+        let _ = parse::parse(&mut program, &source, "Fuzz.vm", options);
+
+        // Splitting the source anywhere should never make the incremental parser panic either.
+        let mut program = VmProgram::new();
+        let mut parser = parse::IncrementalParser::new(&mut program, "Fuzz.vm", options);
+        let middle = (0..=source.len() / 2)
+            .rev()
+            .find(|index| source.is_char_boundary(*index))
+            .unwrap_or(0);
+        let _ = parser
+            .feed(&source[..middle])
+            .and_then(|_| parser.feed(&source[middle..]))
+            .and_then(|_| parser.finish());
+    }
+});
@@ -0,0 +1,237 @@
+//! Reading .vm files out of zip and tar archives, so a whole project can be passed around as one
+//! file.
+
+use crate::{error::CompileError, inflate::inflate, options::Options, parse};
+use std::{error::Error, path::Path};
+
+/// Whether a path looks like an archive this module can read.
+pub fn is_archive(path: &Path) -> bool {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    matches!(extension, Some("zip") | Some("tar"))
+}
+
+/// Returns the name and contents of every .vm file in a zip or tar archive, sorted by name. Names
+/// include any folders the files are in inside the archive.
+pub fn read_vm_files(
+    path: &Path,
+    options: &Options,
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let path_str = path.to_string_lossy();
+    let data = std::fs::read(path)
+        .map_err(|err| CompileError::caused_by(format!("Failed to open \"{}\"", path_str), err))?;
+    let entries = if path.extension().map(|ext| ext == "zip") == Some(true) {
+        zip_entries(&data)
+    } else {
+        tar_entries(&data)
+    };
+    let entries = entries.map_err(|problem| {
+        CompileError::new(format!(
+            "Failed to read the archive \"{}\", caused by:\n{}",
+            path_str, problem
+        ))
+    })?;
+    let mut files = Vec::new();
+    for (name, contents) in entries {
+        // Archives made on macOS come with extra files holding each file's metadata, which have
+        // the same names as the real files.
+        let file_name = name.rsplit('/').next().unwrap_or_default();
+        if name.starts_with("__MACOSX/") || file_name.starts_with("._") {
+            continue;
+        }
+        if !name.to_ascii_lowercase().ends_with(".vm") {
+            continue;
+        }
+        let contents =
+            parse::decode_source(contents, &path.join(&name).to_string_lossy(), options)?;
+        files.push((name, contents));
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn read_u16(data: &[u8], at: usize) -> Result<usize, String> {
+    let bytes = data.get(at..at + 2).ok_or("The archive is truncated.")?;
+    Ok(bytes[0] as usize | (bytes[1] as usize) << 8)
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<usize, String> {
+    Ok(read_u16(data, at)? | read_u16(data, at + 2)? << 16)
+}
+
+/// Reads every file in a zip archive using its central directory.
+fn zip_entries(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    const END_SIGNATURE: &[u8] = b"PK\x05\x06";
+    const ENTRY_SIGNATURE: &[u8] = b"PK\x01\x02";
+    // The end of central directory record is at least 22 bytes long, and may be followed by a
+    // comment.
+    let end = (0..data.len().saturating_sub(21))
+        .rev()
+        .find(|start| data[*start..].starts_with(END_SIGNATURE))
+        .ok_or("The file is not a zip archive.")?;
+    let num_entries = read_u16(data, end + 10)?;
+    let mut position = read_u32(data, end + 16)?;
+    let mut entries = Vec::with_capacity(num_entries);
+    for _ in 0..num_entries {
+        if data.get(position..position + 4) != Some(ENTRY_SIGNATURE) {
+            return Err("The archive's central directory is corrupt.".to_owned());
+        }
+        let method = read_u16(data, position + 10)?;
+        let compressed_size = read_u32(data, position + 20)?;
+        let name_length = read_u16(data, position + 28)?;
+        let extra_length = read_u16(data, position + 30)?;
+        let comment_length = read_u16(data, position + 32)?;
+        let header = read_u32(data, position + 42)?;
+        let name = data.get(position + 46..position + 46 + name_length);
+        let name = String::from_utf8_lossy(name.ok_or("The archive is truncated.")?).into_owned();
+        position += 46 + name_length + extra_length + comment_length;
+
+        // The local header has its own copy of the name and extra field, which can differ in
+        // length from the ones in the central directory.
+        let start = header + 30 + read_u16(data, header + 26)? + read_u16(data, header + 28)?;
+        let compressed = data.get(start..start + compressed_size);
+        let compressed = compressed.ok_or("The archive is truncated.")?;
+        let contents = match method {
+            0 => compressed.to_owned(),
+            8 => inflate(compressed).map_err(|problem| format!("{}: {}", name, problem))?,
+            _ => {
+                return Err(format!(
+                    "{} is compressed with an unsupported method ({}).",
+                    name, method
+                ))
+            }
+        };
+        entries.push((name, contents));
+    }
+    Ok(entries)
+}
+
+/// Reads every regular file in a tar archive.
+fn tar_entries(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    const BLOCK_SIZE: usize = 512;
+    let mut entries = Vec::new();
+    let mut position = 0;
+    let mut long_name = None;
+    while position + BLOCK_SIZE <= data.len() {
+        let header = &data[position..position + BLOCK_SIZE];
+        // The archive ends with blocks of zeros.
+        if header.iter().all(|byte| *byte == 0) {
+            break;
+        }
+        let field = |start: usize, length: usize| {
+            let field = &header[start..start + length];
+            let end = field.iter().position(|byte| *byte == 0).unwrap_or(length);
+            String::from_utf8_lossy(&field[..end]).into_owned()
+        };
+        let size = field(124, 12);
+        let size = usize::from_str_radix(size.trim(), 8)
+            .map_err(|_| format!("The archive has an invalid file size \"{}\".", size))?;
+        let start = position + BLOCK_SIZE;
+        let contents = data.get(start..start + size);
+        let contents = contents.ok_or("The archive is truncated.")?;
+        match header[156] {
+            b'0' | 0 => {
+                // Long names are either split between the name and the prefix in the ustar
+                // format, or come from an extra entry before this one.
+                let name = match (long_name.take(), field(345, 155)) {
+                    (Some(name), _) => name,
+                    (None, prefix) if !prefix.is_empty() && &header[257..262] == b"ustar" => {
+                        format!("{}/{}", prefix, field(0, 100))
+                    }
+                    _ => field(0, 100),
+                };
+                entries.push((name, contents.to_owned()));
+            }
+            // A GNU long name.
+            b'L' => {
+                let name = String::from_utf8_lossy(contents);
+                long_name = Some(name.trim_end_matches('\0').to_owned());
+            }
+            // PAX extended attributes, which are lines like "30 path=some/long/name\n".
+            b'x' => {
+                for record in String::from_utf8_lossy(contents).lines() {
+                    if let Some((_, path)) = record.split_once(" path=") {
+                        long_name = Some(path.to_owned());
+                    }
+                }
+            }
+            _ => (),
+        }
+        // File contents are padded to a whole number of blocks.
+        position = start + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{translate, vm_program::VmProgram};
+
+    /// Makes a zip archive holding files that aren't compressed.
+    fn stored_zip(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut directory = Vec::new();
+        let u16_bytes = |value: usize| (value as u16).to_le_bytes();
+        let u32_bytes = |value: usize| (value as u32).to_le_bytes();
+        for (name, contents) in files {
+            let header = data.len();
+            data.extend_from_slice(b"PK\x03\x04");
+            data.extend_from_slice(&[0; 14]);
+            data.extend_from_slice(&u32_bytes(contents.len()));
+            data.extend_from_slice(&u32_bytes(contents.len()));
+            data.extend_from_slice(&u16_bytes(name.len()));
+            data.extend_from_slice(&u16_bytes(0));
+            data.extend_from_slice(name.as_bytes());
+            data.extend_from_slice(contents.as_bytes());
+            directory.extend_from_slice(b"PK\x01\x02");
+            directory.extend_from_slice(&[0; 16]);
+            directory.extend_from_slice(&u32_bytes(contents.len()));
+            directory.extend_from_slice(&u32_bytes(contents.len()));
+            directory.extend_from_slice(&u16_bytes(name.len()));
+            directory.extend_from_slice(&[0; 12]);
+            directory.extend_from_slice(&u32_bytes(header));
+            directory.extend_from_slice(name.as_bytes());
+        }
+        let directory_start = data.len();
+        data.extend_from_slice(&directory);
+        data.extend_from_slice(b"PK\x05\x06");
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&u16_bytes(files.len()));
+        data.extend_from_slice(&u16_bytes(files.len()));
+        data.extend_from_slice(&u32_bytes(directory.len()));
+        data.extend_from_slice(&u32_bytes(directory_start));
+        data.extend_from_slice(&u16_bytes(0));
+        data
+    }
+
+    #[test]
+    fn compiles_the_files_in_a_zip() {
+        let main = "function Main.main 0\npush constant 0\nreturn\n";
+        let sys = "function Sys.init 0\ncall Main.main 0\nlabel HALT\ngoto HALT\n";
+        let zip = stored_zip(&[
+            ("Prog/Sys.vm", sys),
+            ("Prog/Main.vm", main),
+            ("Prog/notes.txt", "not code"),
+            ("Prog/._Main.vm", "\0\x05\x16\x07"),
+            ("__MACOSX/Prog/._Sys.vm", "\0\x05\x16\x07"),
+        ]);
+        let path = std::env::temp_dir().join(format!("translator-test-{}.zip", std::process::id()));
+        std::fs::write(&path, zip).unwrap();
+        let options = Options::default();
+        let files = read_vm_files(&path, &options).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let expected = vec![
+            ("Prog/Main.vm".to_owned(), main.to_owned()),
+            ("Prog/Sys.vm".to_owned(), sys.to_owned()),
+        ];
+        assert_eq!(files, expected);
+        let mut program = VmProgram::new();
+        for (name, contents) in &files {
+            parse::parse(&mut program, contents, name, &options).unwrap();
+        }
+        let result = translate::translate(program, &options).unwrap();
+        assert!(result.starts_with("// Bootstrap\n"));
+        assert!(result.contains("(Main.main)\n"));
+        assert!(result.contains("(Sys.init)\n"));
+    }
+}
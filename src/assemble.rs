@@ -0,0 +1,395 @@
+use crate::{
+    error::CompileError,
+    layout::{ARG, KEYBOARD, LCL, MEMORY_MAPPED_IO_START, SP, THAT, THIS},
+};
+use std::{collections::HashMap, error::Error};
+
+const PREDEFINED_SYMBOLS: &[(&str, usize)] = &[
+    ("SP", SP),
+    ("LCL", LCL),
+    ("ARG", ARG),
+    ("THIS", THIS),
+    ("THAT", THAT),
+    ("SCREEN", MEMORY_MAPPED_IO_START),
+    ("KBD", KEYBOARD),
+];
+
+/// The a bit and the six c bits of every computation the ALU can do. Commuted versions of the
+/// symmetric operations are accepted too, since they are easier to write by hand.
+const COMPUTATIONS: &[(&str, u16)] = &[
+    ("0", 0b0_101010),
+    ("1", 0b0_111111),
+    ("-1", 0b0_111010),
+    ("D", 0b0_001100),
+    ("A", 0b0_110000),
+    ("!D", 0b0_001101),
+    ("!A", 0b0_110001),
+    ("-D", 0b0_001111),
+    ("-A", 0b0_110011),
+    ("D+1", 0b0_011111),
+    ("A+1", 0b0_110111),
+    ("D-1", 0b0_001110),
+    ("A-1", 0b0_110010),
+    ("D+A", 0b0_000010),
+    ("A+D", 0b0_000010),
+    ("D-A", 0b0_010011),
+    ("A-D", 0b0_000111),
+    ("D&A", 0b0_000000),
+    ("A&D", 0b0_000000),
+    ("D|A", 0b0_010101),
+    ("A|D", 0b0_010101),
+    ("M", 0b1_110000),
+    ("!M", 0b1_110001),
+    ("-M", 0b1_110011),
+    ("M+1", 0b1_110111),
+    ("M-1", 0b1_110010),
+    ("D+M", 0b1_000010),
+    ("M+D", 0b1_000010),
+    ("D-M", 0b1_010011),
+    ("M-D", 0b1_000111),
+    ("D&M", 0b1_000000),
+    ("M&D", 0b1_000000),
+    ("D|M", 0b1_010101),
+    ("M|D", 0b1_010101),
+];
+
+const JUMPS: &[(&str, u16)] = &[
+    ("JGT", 1),
+    ("JEQ", 2),
+    ("JGE", 3),
+    ("JLT", 4),
+    ("JNE", 5),
+    ("JLE", 6),
+    ("JMP", 7),
+];
+
+/// An A instruction whose address can only be known once every label has been seen.
+struct UnresolvedSymbol<'a> {
+    instruction: usize,
+    symbol: &'a str,
+    line: usize,
+}
+
+struct Assembler<'a> {
+    instructions: Vec<u16>,
+    labels: HashMap<&'a str, u16>,
+    unresolved: Vec<UnresolvedSymbol<'a>>,
+    /// The address the first instruction will be loaded at.
+    origin: usize,
+    /// Jumps that go to the address set by the A instruction right before them, as the line of
+    /// the jump and the index of the A instruction.
+    jumps: Vec<(usize, usize)>,
+    /// The `// command: ...` comment that came before each line of the source, so errors can say
+    /// which VM command generated the broken code.
+    commands: Vec<&'a str>,
+}
+
+impl<'a> Assembler<'a> {
+    fn error(&self, line: usize, problem: String) -> Box<dyn Error> {
+        let message = match self.commands[line] {
+            "" => format!("{} (on line {} of the assembly)", problem, line + 1),
+            command => format!(
+                "{} (on line {} of the assembly, generated by \"{}\")",
+                problem,
+                line + 1,
+                command
+            ),
+        };
+        CompileError::new(message).into()
+    }
+
+    fn assemble_c_instruction(&self, line: usize, code: &str) -> Result<u16, Box<dyn Error>> {
+        let (dest, rest) = match code.find('=') {
+            Some(index) => (&code[..index], &code[index + 1..]),
+            None => ("", code),
+        };
+        let (comp, jump) = match rest.find(';') {
+            Some(index) => (&rest[..index], &rest[index + 1..]),
+            None => (rest, ""),
+        };
+        let mut dest_bits = 0;
+        for register in dest.chars() {
+            let bit = match register {
+                'A' => 0b100,
+                'D' => 0b010,
+                'M' => 0b001,
+                _ => return Err(self.error(line, format!("Invalid destination \"{}\"", dest))),
+            };
+            if dest_bits & bit != 0 {
+                return Err(self.error(line, format!("Invalid destination \"{}\"", dest)));
+            }
+            dest_bits |= bit;
+        }
+        let comp_bits = COMPUTATIONS.iter().find(|(name, _)| *name == comp);
+        let comp_bits = comp_bits
+            .ok_or_else(|| self.error(line, format!("Invalid computation \"{}\"", comp)))?
+            .1;
+        let jump_bits = if jump.is_empty() {
+            0
+        } else {
+            let jump_bits = JUMPS.iter().find(|(name, _)| *name == jump);
+            jump_bits
+                .ok_or_else(|| self.error(line, format!("Invalid jump \"{}\"", jump)))?
+                .1
+        };
+        Ok(0b111 << 13 | comp_bits << 6 | dest_bits << 3 | jump_bits)
+    }
+
+    fn assemble_line(&mut self, line: usize, code: &'a str) -> Result<(), Box<dyn Error>> {
+        if let Some(label) = code.strip_prefix('(') {
+            let label = label.strip_suffix(')');
+            let label =
+                label.ok_or_else(|| self.error(line, format!("Invalid label \"{}\"", code)))?;
+            let address = self.origin + self.instructions.len();
+            if address > 32767 {
+                let problem = format!(
+                    "The label \"{}\" would be at address {}, past the end of ROM",
+                    label, address
+                );
+                return Err(self.error(line, problem));
+            }
+            if self.labels.insert(label, address as u16).is_some() {
+                return Err(self.error(line, format!("The label \"{}\" is defined twice", label)));
+            }
+        } else if let Some(value) = code.strip_prefix('@') {
+            if value.starts_with(|c: char| c.is_ascii_digit()) {
+                let value = value.parse::<u16>().ok().filter(|value| *value <= 32767);
+                let value = value.ok_or_else(|| {
+                    self.error(
+                        line,
+                        format!("Invalid or out of range constant \"{}\"", code),
+                    )
+                })?;
+                self.instructions.push(value);
+            } else {
+                self.unresolved.push(UnresolvedSymbol {
+                    instruction: self.instructions.len(),
+                    symbol: value,
+                    line,
+                });
+                self.instructions.push(0);
+            }
+        } else {
+            let instruction = self.assemble_c_instruction(line, code)?;
+            let after_a = self.instructions.len().checked_sub(1);
+            let after_a = after_a.filter(|index| self.instructions[*index] & 0x8000 == 0);
+            if let (Some(index), true) = (after_a, instruction & 0b111 != 0) {
+                self.jumps.push((line, index));
+            }
+            self.instructions.push(instruction);
+        }
+        Ok(())
+    }
+
+    fn resolve_symbols(&mut self) -> Result<(), Box<dyn Error>> {
+        for unresolved in &self.unresolved {
+            let address = if let Some(address) = self.labels.get(unresolved.symbol) {
+                *address
+            } else if let Some((_, address)) = PREDEFINED_SYMBOLS
+                .iter()
+                .find(|(name, _)| *name == unresolved.symbol)
+            {
+                *address as u16
+            } else if let Some(register) = register_number(unresolved.symbol) {
+                register
+            } else {
+                // The translator never uses variables, so a symbol we don't know about is a bug.
+                return Err(self.undefined_symbol(unresolved));
+            };
+            self.instructions[unresolved.instruction] = address;
+        }
+        Ok(())
+    }
+
+    /// Makes sure every jump whose target is known goes somewhere inside the program. A label
+    /// right after the last instruction is allowed, since translated code can end with one.
+    fn check_jumps(&self) -> Result<(), Box<dyn Error>> {
+        let end = self.origin + self.instructions.len();
+        for (line, index) in &self.jumps {
+            let target = self.instructions[*index] as usize;
+            if target < self.origin || target > end {
+                let problem = format!(
+                    "The jump goes to address {}, outside of the program at {} to {}",
+                    target,
+                    self.origin,
+                    end - 1
+                );
+                return Err(self.error(*line, problem));
+            }
+        }
+        Ok(())
+    }
+
+    fn undefined_symbol(&self, unresolved: &UnresolvedSymbol) -> Box<dyn Error> {
+        let problem = format!("Undefined symbol \"{}\"", unresolved.symbol);
+        self.error(unresolved.line, problem)
+    }
+}
+
+/// Returns the address of R0 through R15.
+fn register_number(symbol: &str) -> Option<u16> {
+    let number = symbol.strip_prefix('R')?;
+    let value = number.parse::<u16>().ok()?;
+    // Rejects things like R007.
+    if value < 16 && value.to_string() == number {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// The code part of a line of assembly, without any `//` comment or the whitespace around it.
+pub fn strip_comment(line: &str) -> &str {
+    match line.find("//") {
+        Some(comment_start) => line[..comment_start].trim(),
+        None => line.trim(),
+    }
+}
+
+/// Converts Hack assembly into binary instructions. Unlike a normal Hack assembler, symbols that
+/// are neither labels nor predefined are rejected instead of being turned into variables.
+pub fn assemble(source: &str) -> Result<Vec<u16>, Box<dyn Error>> {
+    assemble_at(source, 0)
+}
+
+/// Like `assemble`, but for code that will be loaded into ROM starting at `origin` instead of 0,
+/// so labels refer to where their instructions will actually end up.
+pub fn assemble_at(source: &str, origin: usize) -> Result<Vec<u16>, Box<dyn Error>> {
+    let mut assembler = Assembler {
+        instructions: Vec::new(),
+        labels: HashMap::new(),
+        unresolved: Vec::new(),
+        origin,
+        jumps: Vec::new(),
+        commands: Vec::new(),
+    };
+    let mut current_command = "";
+    let lines: Vec<_> = source.lines().collect();
+    for line in &lines {
+        if let Some(command) = line.trim().strip_prefix("// command:") {
+            current_command = command.trim();
+        }
+        assembler.commands.push(current_command);
+    }
+    for (index, line) in lines.iter().enumerate() {
+        let code = strip_comment(line);
+        if !code.is_empty() {
+            assembler.assemble_line(index, code)?;
+        }
+    }
+    assembler.resolve_symbols()?;
+    assembler.check_jumps()?;
+    Ok(assembler.instructions)
+}
+
+/// Counts how many instructions some assembly contains, without fully assembling it.
+pub fn count_instructions(source: &str) -> usize {
+    source
+        .lines()
+        .map(strip_comment)
+        .filter(|code| !code.is_empty() && !code.starts_with('('))
+        .count()
+}
+
+/// Formats instructions as a .hack file, one instruction per line written as 16 binary digits.
+pub fn to_hack(instructions: &[u16]) -> String {
+    let mut result = String::with_capacity(instructions.len() * 17);
+    for instruction in instructions {
+        result.push_str(&format!("{:016b}\n", instruction));
+    }
+    result
+}
+
+/// Formats instructions as a Logisim memory image. This is the header `v2.0 raw` on its own line
+/// followed by each instruction as lowercase hexadecimal, eight per line separated by spaces.
+pub fn to_logisim(instructions: &[u16]) -> String {
+    let mut result = String::from("v2.0 raw\n");
+    for line in instructions.chunks(8) {
+        let words: Vec<_> = line.iter().map(|word| format!("{:x}", word)).collect();
+        result.push_str(&words.join(" "));
+        result.push('\n');
+    }
+    result
+}
+
+/// Formats instructions as Intel HEX. Each instruction takes up two bytes (most significant
+/// byte first) so instruction N starts at byte address 2N. Data records hold 16 bytes each and
+/// the file ends with the standard end-of-file record `:00000001FF`.
+pub fn to_intel_hex(instructions: &[u16]) -> String {
+    let mut result = String::new();
+    for (index, line) in instructions.chunks(8).enumerate() {
+        let address = index * 16;
+        let mut bytes = vec![
+            (line.len() * 2) as u8,
+            (address >> 8) as u8,
+            address as u8,
+            0,
+        ];
+        for word in line {
+            bytes.push((word >> 8) as u8);
+            bytes.push(*word as u8);
+        }
+        // The checksum makes all the bytes of the record add up to zero.
+        let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        bytes.push(sum.wrapping_neg());
+        result.push(':');
+        for byte in bytes {
+            result.push_str(&format!("{:02X}", byte));
+        }
+        result.push('\n');
+    }
+    result.push_str(":00000001FF\n");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{options::Options, test_util::translate_source};
+
+    #[test]
+    fn broken_translations_fail_to_assemble() {
+        let result = translate_source("push constant 7\npop local 0\n", &Options::default());
+        assert!(assemble(&result).is_ok());
+        // As if the translator had a typo in the code for push.
+        let broken = result.replacen("M=D", "M=Q", 1);
+        let err = assemble(&broken).unwrap_err().to_string();
+        assert!(err.starts_with("Invalid computation \"Q\""));
+        assert!(err.contains("generated by \"push Constant 7\""));
+    }
+
+    #[test]
+    fn rom_images_hold_the_instructions() {
+        let instructions = assemble("@7\nD=A\n").unwrap();
+        assert_eq!(instructions, vec![7, 0xEC10]);
+        assert_eq!(
+            to_hack(&instructions),
+            "0000000000000111\n1110110000010000\n"
+        );
+        assert_eq!(to_logisim(&instructions), "v2.0 raw\n7 ec10\n");
+        assert_eq!(
+            to_intel_hex(&instructions),
+            ":040000000007EC10F9\n:00000001FF\n"
+        );
+    }
+
+    #[test]
+    fn relocating_shifts_label_addresses() {
+        let source = "@END\n0;JMP\n(END)\n@END\n";
+        assert_eq!(assemble(source).unwrap(), vec![2, 0xEA87, 2]);
+        assert_eq!(assemble_at(source, 100).unwrap(), vec![102, 0xEA87, 102]);
+    }
+
+    #[test]
+    fn jumps_have_to_stay_inside_the_program() {
+        assert!(assemble("@2\n0;JMP\n").is_ok());
+        let err = assemble("@3\nD=A\n@500\nD;JGT\n").unwrap_err().to_string();
+        assert!(
+            err.contains("The jump goes to address 500, outside of the program at 0 to 3"),
+            "{}",
+            err
+        );
+        // Addresses don't need to be checked when the value in A isn't known.
+        assert!(assemble("@SP\nA=M\n0;JMP\n").is_ok());
+    }
+}
@@ -0,0 +1,154 @@
+use crate::json;
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+/// A position in a source file. Lines and columns start at 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+/// A problem found while compiling, optionally pointing at the code that caused it.
+#[derive(Clone, Debug)]
+pub struct CompileError {
+    pub severity: Severity,
+    pub message: String,
+    pub location: Option<Location>,
+    /// The error that caused this one, like the IO error behind a file that couldn't be opened.
+    /// Its description is already part of `message`, this is for callers that want to look at it
+    /// through `Error::source`.
+    pub cause: Option<Arc<dyn Error + Send + Sync>>,
+}
+
+impl CompileError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            location: None,
+            cause: None,
+        }
+    }
+
+    pub fn at(message: impl Into<String>, location: Location) -> Self {
+        Self {
+            location: Some(location),
+            ..Self::new(message)
+        }
+    }
+
+    /// An error caused by another one, which is described at the end of the message.
+    pub fn caused_by(
+        message: impl Into<String>,
+        cause: impl Error + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            message: format!("{}, caused by:\n{}", message.into(), cause),
+            cause: Some(Arc::new(cause)),
+            ..Self::new("")
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::new(message)
+        }
+    }
+
+    /// Like the normal `Display` output, but using ANSI escape codes to color the message and
+    /// its location.
+    pub fn to_colored_string(&self) -> String {
+        let color = match self.severity {
+            Severity::Error => "\x1b[31m",
+            Severity::Warning => "\x1b[33m",
+        };
+        let mut result = format!("{}{}\x1b[0m", color, self.message);
+        if let Some(location) = &self.location {
+            result.push_str(&format!(
+                "\nEncountered at \x1b[36m{}:{}:{}\x1b[0m",
+                location.file, location.line, location.col
+            ));
+        }
+        result
+    }
+
+    /// Formats the error as a single-line JSON object for consumption by other tools.
+    pub fn to_json(&self) -> String {
+        let (file, line, col) = match &self.location {
+            Some(location) => (
+                json::string(&location.file),
+                location.line.to_string(),
+                location.col.to_string(),
+            ),
+            None => ("null".to_owned(), "null".to_owned(), "null".to_owned()),
+        };
+        format!(
+            "{{\"file\":{},\"line\":{},\"col\":{},\"message\":{},\"severity\":{}}}",
+            file,
+            line,
+            col,
+            json::string(&self.message),
+            json::string(self.severity.name())
+        )
+    }
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(location) = &self.location {
+            write!(
+                f,
+                "\nEncountered at {}:{}:{}",
+                location.file, location.line, location.col
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for CompileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        let cause = self.cause.as_deref()?;
+        Some(cause)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{options::Options, parse, vm_program::VmProgram};
+
+    #[test]
+    fn json_errors_have_every_field() {
+        let mut program = VmProgram::new();
+        let source = "push constant 1\npush locl 0\n";
+        let err = parse::parse(&mut program, source, "Broken.vm", &Options::default()).unwrap_err();
+        let err = err.downcast::<super::CompileError>().unwrap();
+        let json = err.to_json();
+        assert!(json.starts_with("{\"file\":\"Broken.vm\",\"line\":2,\"col\":6,\"message\":\""));
+        assert!(json.ends_with("\",\"severity\":\"error\"}"));
+        assert!(json.contains("locl"));
+        assert!(!json.contains('\n'));
+    }
+}
@@ -0,0 +1,225 @@
+//! A small decoder for DEFLATE (RFC 1951) compressed data, which is what zip files normally use.
+
+const LENGTH_BASES: [usize; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASES: [usize; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order the lengths of the code length code are stored in.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+const MAX_BITS: usize = 15;
+
+type InflateResult<T = ()> = Result<T, String>;
+
+/// Reads bits starting from the least significant bit of each byte, like DEFLATE wants.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u32,
+    buffered_bits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: u32) -> InflateResult<usize> {
+        while self.buffered_bits < count {
+            let byte = *self
+                .data
+                .get(self.position)
+                .ok_or("The compressed data ended unexpectedly.")?;
+            self.position += 1;
+            self.buffer |= (byte as u32) << self.buffered_bits;
+            self.buffered_bits += 8;
+        }
+        let value = self.buffer & ((1 << count) - 1);
+        self.buffer >>= count;
+        self.buffered_bits -= count;
+        Ok(value as usize)
+    }
+
+    /// Throws away what is left of the current byte.
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.buffered_bits = 0;
+    }
+}
+
+/// A canonical Huffman code, stored as how many codes there are of each length along with the
+/// symbols sorted by their codes.
+struct Huffman {
+    counts: [usize; MAX_BITS + 1],
+    symbols: Vec<usize>,
+}
+
+impl Huffman {
+    fn new(lengths: &[usize]) -> Self {
+        let mut counts = [0; MAX_BITS + 1];
+        for length in lengths {
+            counts[*length] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0; MAX_BITS + 1];
+        for length in 1..MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length]] = symbol;
+                offsets[*length] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> InflateResult<usize> {
+        // The first code of the current length, and where its symbol is in `symbols`.
+        let mut code = 0;
+        let mut first = 0;
+        let mut index = 0;
+        for length in 1..=MAX_BITS {
+            code |= reader.bits(1)?;
+            let count = self.counts[length];
+            if code < first + count {
+                return Ok(self.symbols[index + code - first]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("The compressed data contains an invalid code.".to_owned())
+    }
+}
+
+fn inflate_stored(reader: &mut BitReader, output: &mut Vec<u8>) -> InflateResult {
+    reader.align();
+    let start = reader.position;
+    let header = reader.data.get(start..start + 4);
+    let header = header.ok_or("The compressed data ended unexpectedly.")?;
+    let length = header[0] as usize | (header[1] as usize) << 8;
+    let complement = header[2] as usize | (header[3] as usize) << 8;
+    if length != !complement & 0xFFFF {
+        return Err("The compressed data has a corrupt block length.".to_owned());
+    }
+    let data = reader.data.get(start + 4..start + 4 + length);
+    output.extend_from_slice(data.ok_or("The compressed data ended unexpectedly.")?);
+    reader.position = start + 4 + length;
+    Ok(())
+}
+
+fn inflate_codes(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> InflateResult {
+    loop {
+        let symbol = literals.decode(reader)?;
+        if symbol < 256 {
+            output.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let symbol = symbol - 257;
+            if symbol >= LENGTH_BASES.len() {
+                return Err("The compressed data contains an invalid length.".to_owned());
+            }
+            let length = LENGTH_BASES[symbol] + reader.bits(LENGTH_EXTRA_BITS[symbol])?;
+            let symbol = distances.decode(reader)?;
+            if symbol >= DISTANCE_BASES.len() {
+                return Err("The compressed data contains an invalid distance.".to_owned());
+            }
+            let distance = DISTANCE_BASES[symbol] + reader.bits(DISTANCE_EXTRA_BITS[symbol])?;
+            if distance > output.len() {
+                return Err("The compressed data refers to data before its start.".to_owned());
+            }
+            // The copy can overlap with itself, so it has to go one byte at a time.
+            for _ in 0..length {
+                output.push(output[output.len() - distance]);
+            }
+        }
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> InflateResult<(Huffman, Huffman)> {
+    let num_literals = reader.bits(5)? + 257;
+    let num_distances = reader.bits(5)? + 1;
+    let num_code_lengths = reader.bits(4)? + 4;
+    let mut code_lengths = [0; 19];
+    for index in &CODE_LENGTH_ORDER[..num_code_lengths] {
+        code_lengths[*index] = reader.bits(3)?;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+    let mut lengths = Vec::with_capacity(num_literals + num_distances);
+    while lengths.len() < num_literals + num_distances {
+        let (length, repeat) = match code_lengths.decode(reader)? {
+            length @ 0..=15 => (length, 1),
+            16 => {
+                let previous = lengths.last();
+                let previous = previous.ok_or("The compressed data repeats a missing length.")?;
+                (*previous, 3 + reader.bits(2)?)
+            }
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(length, repeat));
+    }
+    if lengths.len() > num_literals + num_distances {
+        return Err("The compressed data has too many code lengths.".to_owned());
+    }
+    let (literals, distances) = lengths.split_at(num_literals);
+    Ok((Huffman::new(literals), Huffman::new(distances)))
+}
+
+/// Decompresses raw DEFLATE data, without any zlib or gzip header.
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = BitReader {
+        data,
+        position: 0,
+        buffer: 0,
+        buffered_bits: 0,
+    };
+    let mut output = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => inflate_stored(&mut reader, &mut output)?,
+            1 => {
+                let (literals, distances) = fixed_codes();
+                inflate_codes(&mut reader, &mut output, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut reader)?;
+                inflate_codes(&mut reader, &mut output, &literals, &distances)?;
+            }
+            _ => return Err("The compressed data has an invalid block type.".to_owned()),
+        }
+        if last {
+            return Ok(output);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+//! Where everything the translated code uses is in the Hack computer's memory, for tools that
+//! need to look at it from the outside like emulators and debuggers.
+
+/// Address of the stack pointer, which points just past the top of the stack.
+pub const SP: usize = 0;
+/// Address of the pointer to the current function's local segment.
+pub const LCL: usize = 1;
+/// Address of the pointer to the current function's argument segment.
+pub const ARG: usize = 2;
+/// Address of the pointer to the this segment, which is also `pointer 0`.
+pub const THIS: usize = 3;
+/// Address of the pointer to the that segment, which is also `pointer 1`.
+pub const THAT: usize = 4;
+/// Address of `temp 0`. The temp segment is 8 values long.
+pub const TEMP_START: usize = 5;
+/// Address of R13, the first of the three registers from R13 to R15 the translated code uses for
+/// its own scratch work.
+pub const SCRATCH_START: usize = 13;
+
+/// Address of the first static variable.
+pub const STATIC_MEMORY_START: usize = 16;
+/// Address of the bottom of the stack, which is also where the static segment ends.
+pub const STACK_MEMORY_START: usize = 256;
+/// Address of the start of the heap, which is also where the stack ends.
+pub const HEAP_MEMORY_START: usize = 2048;
+/// Address of the start of the screen, which is also where the heap ends.
+pub const MEMORY_MAPPED_IO_START: usize = 16384;
+/// Address of the keyboard, which holds the key currently pressed.
+pub const KEYBOARD: usize = 24576;
+/// How many instructions fit in the ROM the program runs from.
+pub const ROM_SIZE: usize = 32768;
+/// How many values fit on the stack.
+pub const STACK_MEMORY_SIZE: usize = HEAP_MEMORY_START - STACK_MEMORY_START;
+/// How many static variables fit before they start overwriting the stack.
+pub const STATIC_MEMORY_SIZE: usize = STACK_MEMORY_START - STATIC_MEMORY_START;
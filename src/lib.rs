@@ -0,0 +1,21 @@
+//! Translates programs written for the NAND To Tetris virtual machine into Hack assembly.
+
+pub mod archive;
+pub mod assemble;
+pub mod error;
+mod inflate;
+mod json;
+pub mod layout;
+pub mod optimize;
+pub mod options;
+pub mod parse;
+pub mod profile;
+pub mod self_test;
+pub mod stack_depth;
+pub mod stats;
+#[cfg(test)]
+mod test_util;
+pub mod trace;
+pub mod translate;
+pub mod validate;
+pub mod vm_program;
@@ -1,101 +1,1269 @@
-use crate::vm_program::VmProgram;
-use std::{error::Error, path::Path};
+use std::{
+    error::Error,
+    io::{stderr, IsTerminal},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use translator::{
+    archive, assemble,
+    error::{CompileError, Severity},
+    layout, optimize,
+    options::{self, ColorChoice, ErrorFormat, LineEnding, Options},
+    parse,
+    profile::Profile,
+    self_test,
+    stack_depth::StackReport,
+    stats::Statistics,
+    trace, translate, validate,
+    vm_program::VmProgram,
+};
 
-mod parse;
-mod translate;
-mod vm_program;
+fn read_file(options: &Options, path: &Path) -> Result<String, Box<dyn Error>> {
+    let contents = std::fs::read(path);
+    let contents = contents.map_err(|err| {
+        CompileError::caused_by(
+            format!("Failed to open \"{}\"", path.to_string_lossy()),
+            err,
+        )
+    })?;
+    parse::decode_source(contents, &path.to_string_lossy(), options)
+}
+
+/// A .vm file that will be part of the program.
+struct SourceFile {
+    path: PathBuf,
+    /// The contents of files that came from an archive. Other files are read when needed.
+    contents: Option<String>,
+    /// What to say when including the file, if anything.
+    message: Option<&'static str>,
+}
+
+impl SourceFile {
+    fn read(&self, options: &Options) -> Result<String, Box<dyn Error>> {
+        match &self.contents {
+            Some(contents) => Ok(contents.clone()),
+            None => read_file(options, &self.path),
+        }
+    }
+}
 
-fn add_file(to: &mut VmProgram, path: &Path) -> Result<(), Box<dyn Error>> {
-    let path_str = path.to_string_lossy().to_owned();
+/// Parses a file into the program, returning how many commands it had.
+fn add_file(
+    options: &Options,
+    profile: &mut Profile,
+    to: &mut VmProgram,
+    file: &SourceFile,
+) -> Result<usize, Box<dyn Error>> {
+    let path_str = file.path.to_string_lossy().into_owned();
     let path_str = &path_str[..];
-    let contents = std::fs::read_to_string(path);
-    let contents =
-        contents.map_err(|err| format!("Failed to open \"{}\", caused by:\n{}", path_str, err))?;
-    parse::parse(to, &contents[..], path_str)?;
+    let start = Instant::now();
+    let contents = file.read(options)?;
+    profile.record("Reading files", start);
+    let previous_count = to.commands.len();
+    let start = Instant::now();
+    parse::parse(to, &contents[..], path_str, options)?;
+    profile.record("Parsing", start);
+    let added = to.commands.len() - previous_count;
+    if options.verbose {
+        println!("{}", describe_command_count(added, to.commands.len()));
+    }
+    Ok(added)
+}
+
+/// What `--verbose` says about the commands in a file after it is parsed.
+fn describe_command_count(added: usize, total: usize) -> String {
+    format!("    {} commands ({} total so far)", added, total)
+}
+
+/// Prints every token the parser would see in a file, to help debug the parser.
+fn dump_tokens(options: &Options, file: &SourceFile) -> Result<(), Box<dyn Error>> {
+    let path_str = file.path.to_string_lossy();
+    let contents = file.read(options)?;
+    for (line, col, token) in parse::tokens(&contents, options) {
+        println!("{}:{}:{}\t{}", path_str, line, col, token);
+    }
     Ok(())
 }
 
-fn entry() -> Result<(), Box<dyn Error>> {
-    let arg1 = std::env::args().skip(1).next();
-    let source_path_str = arg1.ok_or(format!("Must specify a file or folder."))?;
-    let source_path = Path::new(&source_path_str[..]);
+/// Prints every function the files define, for `--list-functions`.
+fn list_functions(options: &Options, files: &[SourceFile]) -> Result<(), Box<dyn Error>> {
+    for line in function_list(options, files)? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// The lines `--list-functions` prints, one for each function. The files are only parsed, since
+/// the functions are all known by then.
+fn function_list(options: &Options, files: &[SourceFile]) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut lines = Vec::new();
+    let mut program = VmProgram::new();
+    for file in files {
+        add_file(options, &mut Profile::default(), &mut program, file)?;
+    }
+    for function in program.functions() {
+        let name = match &function.name {
+            Some(name) => name,
+            None => continue,
+        };
+        // The body starts after the label and the setup of the function.
+        let location = program.location(function.start - 2).map(|mut location| {
+            location.col = location.col - 1 + options.column_base;
+            location
+        });
+        match options.error_format {
+            ErrorFormat::Human => {
+                let location = match &location {
+                    Some(location) => {
+                        format!("{}:{}:{}", location.file, location.line, location.col)
+                    }
+                    None => "?".to_owned(),
+                };
+                lines.push(format!("{}\t{}\t{}", location, name, function.num_locals));
+            }
+            ErrorFormat::Json => lines.push(function.to_json(location.as_ref())),
+        }
+    }
+    Ok(lines)
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+const NOTHING_INCLUDED: &str = "None of the .vm files are left after --include-only and --exclude.";
+
+/// Lists the .vm files in a folder, sorted by name so the output doesn't depend on the order the
+/// file system happens to return them in.
+fn vm_files_in(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let dir_str = dir.to_string_lossy();
+    let reader = dir.read_dir().map_err(|err| {
+        CompileError::caused_by(format!("Failed to view directory \"{}\"", dir_str), err)
+    })?;
+    let mut files = Vec::new();
+    for entry in reader {
+        let entry = entry.map_err(|err| {
+            let message = format!("Failed to view item in directory \"{}\"", dir_str);
+            CompileError::caused_by(message, err)
+        })?;
+        let path = entry.path();
+        if path.is_file() && has_vm_extension(&path) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Whether a path ends in `.vm`, in any case. Files copied from a case-insensitive file system
+/// sometimes end up as `.VM`.
+fn has_vm_extension(path: &Path) -> bool {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    extension.is_some_and(|extension| extension.eq_ignore_ascii_case("vm"))
+}
+
+/// Static variables that don't fit in the static segment silently overwrite the stack, so we
+/// report how much of it is used and complain when it gets close to full. Being close to full is
+/// only a warning, which is returned for the caller to report.
+fn check_static_size(
+    options: &Options,
+    program: &VmProgram,
+) -> Result<Option<CompileError>, Box<dyn Error>> {
+    let used = program.static_slots_used();
+    let available = layout::STATIC_MEMORY_SIZE;
+    println!("Static memory usage: {}/{} slots", used, available);
+    if used > available {
+        Err(format!(
+            "The program uses {} static variables, but only {} fit in memory.",
+            used, available
+        ))?;
+    } else if let Some(max) = options.max_static.filter(|max| used > *max) {
+        Err(format!(
+            "The program uses {} static variables, more than the limit of {} set by --max-static.",
+            used, max
+        ))?;
+    } else if used * 10 > available * 9 {
+        let message = format!(
+            "The program uses {} of the {} available static variable slots.",
+            used, available
+        );
+        return Ok(Some(CompileError::warning(message)));
+    }
+    Ok(None)
+}
+
+/// The program has to fit in ROM to run, counting where `--relocate` puts it.
+fn check_rom_size(options: &Options, instructions: usize) -> Result<(), Box<dyn Error>> {
+    let end = options.relocate + instructions;
+    if end > layout::ROM_SIZE {
+        let after = match options.relocate {
+            0 => String::new(),
+            relocate => format!(" after address {}", relocate),
+        };
+        Err(format!(
+            "The program is {} instructions long, {} more than the {} that fit in ROM{}.",
+            instructions,
+            end - layout::ROM_SIZE,
+            layout::ROM_SIZE - options.relocate,
+            after
+        ))?;
+    }
+    Ok(())
+}
+
+/// Everything is built with `\n` line endings, which are only changed here if they should be
+/// something else.
+fn write_output(options: &Options, path: &Path, contents: &str) -> Result<(), Box<dyn Error>> {
+    let result = match options.line_ending {
+        LineEnding::Lf => std::fs::write(path, contents),
+        LineEnding::Crlf => std::fs::write(path, contents.replace('\n', "\r\n")),
+    };
+    let path = path.to_string_lossy();
+    result.map_err(|err| {
+        CompileError::caused_by(format!("Failed to write result to \"{}\"", path), err)
+    })?;
+    println!("Wrote output to \"{}\"", path);
+    Ok(())
+}
+
+/// Prints a warning or error in the format the user asked for.
+fn report(options: &Options, diagnostic: &CompileError) {
+    eprintln!("{}", format_diagnostic(options, diagnostic));
+}
+
+/// What `report` prints for a warning or error.
+fn format_diagnostic(options: &Options, diagnostic: &CompileError) -> String {
+    let color = match options.color {
+        ColorChoice::Auto => stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+    };
+    match options.error_format {
+        ErrorFormat::Human => {
+            let text = if color {
+                diagnostic.to_colored_string()
+            } else {
+                diagnostic.to_string()
+            };
+            match diagnostic.severity {
+                Severity::Error => format!("Encountered an error:\n{}", text),
+                Severity::Warning => format!("Warning: {}", text),
+            }
+        }
+        ErrorFormat::Json => diagnostic.to_json(),
+    }
+}
 
+/// Parses and checks the files that make up a program.
+fn load_program(
+    options: &Options,
+    profile: &mut Profile,
+    files: &[SourceFile],
+) -> Result<VmProgram, Box<dyn Error>> {
     let mut program = VmProgram::new();
-    if source_path.is_file() {
-        if !source_path_str.ends_with(".vm") {
+    // The slots before the base belong to other programs, so they count as used.
+    program.static_size = options.static_base;
+    for file in files {
+        if let Some(message) = file.message {
+            println!("{} {}...", message, file.path.to_string_lossy());
+        }
+        add_file(options, profile, &mut program, file)?;
+    }
+
+    let start = Instant::now();
+    if let Some(warning) = check_static_size(options, &program)? {
+        report(options, &warning);
+    }
+    for warning in validate::merge_adjacent_labels(&mut program, options) {
+        report(options, &warning);
+    }
+    validate::validate(&program, options)?;
+    if options.case_insensitive {
+        validate::resolve_names_ignoring_case(&mut program);
+    }
+    for warning in validate::lint(&program, options) {
+        report(options, &warning);
+    }
+    profile.record("Validation", start);
+
+    let start = Instant::now();
+    for (pass, change) in optimize::optimize(&mut program, options) {
+        if options.verbose {
+            println!("{}: {:+} commands", pass, change);
+        }
+    }
+    profile.record("Optimization", start);
+    Ok(program)
+}
+
+/// Translates each file on its own, like the first part of the VM translator assignment. Since
+/// none of them is a whole program there is no bootstrap code. The labels the translator makes up
+/// are named after the file they are in, so that the outputs don't define the same ones when they
+/// are put together. For the same reason comparisons aren't shared, even with `-O2`.
+fn translate_per_file(
+    options: &Options,
+    profile: &mut Profile,
+    files: &[SourceFile],
+) -> Result<(), Box<dyn Error>> {
+    if files.iter().any(|file| file.contents.is_some()) {
+        Err("--per-file can only be used with folders, not archives.")?;
+    }
+    if options.os_dir.is_some() || options.output.is_some() || options.depfile.is_some() {
+        Err("--per-file can't be combined with --os-dir, -o, or --depfile.")?;
+    }
+    let options = Options {
+        bootstrap: false,
+        require_entry: false,
+        deterministic_labels: true,
+        shared_comparisons: false,
+        ..options.clone()
+    };
+    for file in files {
+        let program = load_program(&options, profile, std::slice::from_ref(file))?;
+        let start = Instant::now();
+        let result = translate::translate(program, &options)?;
+        profile.record("Translation", start);
+        let start = Instant::now();
+        write_output(&options, &file.path.with_extension("asm"), &result)?;
+        profile.record("Writing output", start);
+    }
+    if options.profile {
+        println!("{}", profile);
+    }
+    Ok(())
+}
+
+/// A Makefile rule saying that the outputs are made from the inputs, for `--depfile`.
+fn depfile_rule(outputs: &[PathBuf], inputs: &[&Path]) -> String {
+    // Make splits on spaces and treats `#` as a comment and `$` as a variable.
+    let escape = |path: &Path| {
+        let path = path.to_string_lossy();
+        path.replace('$', "$$")
+            .replace(' ', "\\ ")
+            .replace('#', "\\#")
+    };
+    let outputs: Vec<_> = outputs.iter().map(|path| escape(path)).collect();
+    let mut rule = format!("{}:", outputs.join(" "));
+    for input in inputs {
+        rule.push_str(" \\\n  ");
+        rule.push_str(&escape(input));
+    }
+    rule.push('\n');
+    rule
+}
+
+/// Runs `--self-test`, printing how each built in program did.
+fn run_self_test() -> Result<(), Box<dyn Error>> {
+    let results = self_test::run();
+    let mut failed = 0;
+    for (name, result) in &results {
+        match result {
+            Ok(()) => println!("{}: ok", name),
+            Err(problem) => {
+                println!("{}: FAILED, {}", name, problem);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        Err(format!(
+            "{} of {} self tests failed.",
+            failed,
+            results.len()
+        ))?;
+    }
+    println!("All {} self tests passed.", results.len());
+    Ok(())
+}
+
+fn entry(options: &Options) -> Result<(), Box<dyn Error>> {
+    if options.self_test {
+        return run_self_test();
+    }
+    let source_path_str = &options.source_path;
+    let source_path = Path::new(&source_path_str[..]);
+
+    let mut files = Vec::new();
+    let on_disk = |path, message| SourceFile {
+        path,
+        contents: None,
+        message,
+    };
+    if source_path.is_file() && archive::is_archive(source_path) {
+        let found = archive::read_vm_files(source_path, options)?;
+        if found.is_empty() {
+            return Err("The provided archive contains no .vm files.".into());
+        }
+        let included = |name: &String| {
+            let file_name = Path::new(name).file_name().unwrap_or_default();
+            options.includes_file(&file_name.to_string_lossy())
+        };
+        let found: Vec<_> = found
+            .into_iter()
+            .filter(|(name, _)| included(name))
+            .collect();
+        if found.is_empty() {
+            Err(NOTHING_INCLUDED)?;
+        }
+        for (name, contents) in found {
+            files.push(SourceFile {
+                path: source_path.join(name),
+                contents: Some(contents),
+                message: Some("Including file"),
+            });
+        }
+    } else if source_path.is_file() {
+        if !has_vm_extension(source_path) {
             Err(format!(
-                "The file \"{}\" has the wrong extension (expected .vm).",
+                "The file \"{}\" has the wrong extension (expected .vm, .zip, or .tar).",
                 source_path_str
             ))?;
         }
-        add_file(&mut program, source_path)?;
+        files.push(on_disk(source_path.to_owned(), None));
     } else {
-        let reader = source_path.read_dir();
-        let reader = reader.map_err(|err| {
-            format!(
-                "Failed to view directory \"{}\", caused by:\n{}",
-                source_path_str, err
-            )
-        })?;
-        let mut any = false;
-        for entry in reader {
-            let entry = entry.map_err(|err| {
-                format!(
-                    "Failed to view item in directory \"{}\", caused by:\n{}",
-                    source_path_str, err
-                )
-            })?;
-            let path = entry.path();
-            // I dont know why this is necessary VVVVVVVVVVVVVVVVVVVVVVVVVVVVVV but hey it works.
-            if path.is_file() && path.extension().map(|ext| ext == "vm") == Some(true) {
-                println!("Including file {}...", path.to_string_lossy());
-                add_file(&mut program, &path)?;
-                any = true;
+        let found = vm_files_in(source_path)?;
+        if found.is_empty() {
+            return Err("The provided directory contains no .vm files.".into());
+        }
+        let included = |path: &PathBuf| {
+            let file_name = path.file_name().unwrap_or_default();
+            options.includes_file(&file_name.to_string_lossy())
+        };
+        let found: Vec<_> = found.into_iter().filter(included).collect();
+        if found.is_empty() {
+            Err(NOTHING_INCLUDED)?;
+        }
+        files.extend(
+            found
+                .into_iter()
+                .map(|path| on_disk(path, Some("Including file"))),
+        );
+    }
+    if let Some(os_dir) = &options.os_dir {
+        for path in vm_files_in(Path::new(os_dir))? {
+            // The program's own version of an OS file replaces the standard one, even if the
+            // extensions are in different cases.
+            let overridden = files
+                .iter()
+                .any(|file| file.path.file_stem() == path.file_stem());
+            if !overridden {
+                files.push(on_disk(path, Some("Including OS file")));
             }
         }
-        if !any {
-            return Err(format!("The provided directory contains no .vm files.").into());
+    }
+
+    if options.dump_tokens {
+        for file in &files {
+            dump_tokens(options, file)?;
         }
+        return Ok(());
+    }
+    if options.list_functions {
+        return list_functions(options, &files);
     }
+    let mut profile = Profile::default();
+    if options.per_file {
+        return translate_per_file(options, &mut profile, &files);
+    }
+    let program = load_program(options, &mut profile, &files)?;
 
     // Optional printing of intermediate representation.
     if cfg!(feature = "dump") {
         println!("\nInternal Representation:\n{:#?}\n", program);
     }
-    let result = translate::translate(program)?;
+    let mut statistics = Statistics::of_program(&program);
+    let stack_report = if options.stack_report {
+        Some(StackReport::of_program(&program))
+    } else {
+        None
+    };
+    let start = Instant::now();
+    let result = if options.emit_comments_only {
+        let commands = program.commands.iter();
+        commands
+            .map(|command| format!("// command: {}\n", command))
+            .collect()
+    } else if options.emit_stack_trace {
+        trace::trace(&program)
+    } else if options.emit_vm_normalized {
+        program.to_vm_source()
+    } else {
+        translate::translate(program, options)?
+    };
+    profile.record("Translation", start);
     if cfg!(feature = "dump") {
         println!("Translated Program:\n{}\n", result);
     }
+    let binary_wanted = options.emit_hack || options.emit_logisim || options.emit_ihex;
+    let not_assembly =
+        options.emit_comments_only || options.emit_stack_trace || options.emit_vm_normalized;
+    // Anything else isn't made of instructions, so the count stays at 0.
+    if !not_assembly {
+        statistics.instructions = assemble::count_instructions(&result);
+    }
+    let assembled = !not_assembly && (options.verify || binary_wanted);
+    if assembled || (options.check_rom_size && !not_assembly) {
+        // Before assembling, which would only complain about the first label that doesn't fit.
+        check_rom_size(options, statistics.instructions)?;
+    }
+    let instructions = if assembled {
+        let start = Instant::now();
+        let instructions = assemble::assemble_at(&result, options.relocate)?;
+        profile.record("Assembling", start);
+        Some(instructions)
+    } else {
+        None
+    };
+    if let (true, Some(instructions)) = (options.verify, &instructions) {
+        println!(
+            "Verified that the output assembles into {} instructions.",
+            instructions.len()
+        );
+    }
 
-    let output_path = if source_path.is_file() {
+    let output_path = if let Some(output) = &options.output {
+        PathBuf::from(output)
+    } else if source_path.is_file() {
         source_path.with_extension("asm")
     } else {
-        let folder_name = source_path.file_name().unwrap().to_string_lossy();
+        // Paths like `.` end in something other than a name, but the absolute path they refer to
+        // often doesn't.
+        let absolute = source_path.canonicalize().ok();
+        let folder_name = source_path.file_name();
+        let folder_name = match folder_name.or_else(|| absolute.as_deref()?.file_name()) {
+            Some(folder_name) => folder_name.to_string_lossy(),
+            None => Err(format!(
+                "The folder \"{}\" has no name to call the output after, use -o to say where to write it.",
+                source_path_str
+            ))?,
+        };
         // A file inside the folder called FolderName.asm
         source_path.join(format!("{}.asm", folder_name))
     };
-    let result = std::fs::write(&output_path, result);
-    let output_path = output_path.to_string_lossy();
-    result.map_err(|err| {
-        format!(
-            "Failed to write result to \"{}\", caused by:\n{:?}",
-            output_path, err
-        )
-    })?;
-    println!("Wrote output to \"{}\"", output_path);
+    if files.iter().any(|file| same_file(&file.path, &output_path)) {
+        Err(format!(
+            "Writing the output to \"{}\" would overwrite one of the input files.",
+            output_path.to_string_lossy()
+        ))?;
+    }
+    let start = Instant::now();
+    write_output(options, &output_path, &result)?;
+    let mut outputs = vec![output_path.clone()];
+    if let Some(instructions) = &instructions {
+        if options.emit_hack {
+            let hack = assemble::to_hack(instructions);
+            outputs.push(output_path.with_extension("hack"));
+            write_output(options, &output_path.with_extension("hack"), &hack)?;
+        }
+        if options.emit_logisim {
+            let image = assemble::to_logisim(instructions);
+            outputs.push(output_path.with_extension("rom"));
+            write_output(options, &output_path.with_extension("rom"), &image)?;
+        }
+        if options.emit_ihex {
+            let hex = assemble::to_intel_hex(instructions);
+            outputs.push(output_path.with_extension("hex"));
+            write_output(options, &output_path.with_extension("hex"), &hex)?;
+        }
+    }
+    if let Some(depfile) = &options.depfile {
+        let inputs = files.iter().map(|file| match file.contents {
+            // Files from an archive only change when the archive does.
+            Some(_) => source_path,
+            None => file.path.as_path(),
+        });
+        let mut inputs: Vec<_> = inputs.collect();
+        inputs.dedup();
+        let rule = depfile_rule(&outputs, &inputs);
+        std::fs::write(depfile, rule).map_err(|err| {
+            let message = format!("Failed to write the dependency file \"{}\"", depfile);
+            CompileError::caused_by(message, err)
+        })?;
+    }
+    profile.record("Writing output", start);
+
+    if options.stats {
+        println!("{}", statistics);
+    }
+    if let Some(stack_report) = stack_report {
+        println!("{}", stack_report);
+    }
+    if options.profile {
+        println!("{}", profile);
+    }
+    match options.stats_json.as_deref() {
+        None => (),
+        Some("") => println!("{}", statistics.to_json()),
+        Some(path) => {
+            let result = std::fs::write(path, statistics.to_json());
+            result.map_err(|err| {
+                let message = format!("Failed to write statistics to \"{}\"", path);
+                CompileError::caused_by(message, err)
+            })?;
+        }
+    }
     Ok(())
 }
 
+/// Parses the command line, along with the project configuration file in the folder being
+/// translated or the one the file being translated is in, if there is one.
+fn parse_options(args: Vec<String>) -> Result<Options, Box<dyn Error>> {
+    let options = Options::from_args(args.clone())?;
+    if options.source_path.is_empty() {
+        return Ok(options);
+    }
+    let source_path = Path::new(&options.source_path);
+    let project_dir = if source_path.is_dir() {
+        source_path
+    } else {
+        source_path.parent().unwrap_or_else(|| Path::new(""))
+    };
+    let config_path = project_dir.join(options::CONFIG_FILE_NAME);
+    if !config_path.is_file() {
+        return Ok(options);
+    }
+    let source = read_file(&options, &config_path)?;
+    let mut config = options::parse_config(&source)?;
+    // Paths in the configuration are relative to the folder it is in. An empty --stats-json means
+    // printing the statistics rather than writing them to a file.
+    let is_path = |name: &str| matches!(name, "os-dir" | "output" | "depfile" | "stats-json");
+    for (name, value) in &mut config {
+        if let (true, Some(value)) = (is_path(name), value) {
+            if !value.is_empty() {
+                *value = project_dir.join(&value).to_string_lossy().into_owned();
+            }
+        }
+    }
+    // The arguments on their own were fine, so any problem is from the configuration.
+    Options::from_args_and_config(args, config).map_err(|err| {
+        let path = config_path.to_string_lossy();
+        format!("In \"{}\": {}", path, err).into()
+    })
+}
+
 fn main() {
-    match entry() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (options, result) = match parse_options(args.clone()) {
+        Ok(options) => {
+            let result = entry(&options);
+            (options, result)
+        }
+        // The problem might only be in the configuration file, so the arguments can still say
+        // how it should be reported.
+        Err(err) => (Options::from_args(args).unwrap_or_default(), Err(err)),
+    };
+    match result {
         Ok(_) => {
             println!("Operation completed sucessfully.");
             std::process::exit(0);
         }
         Err(err) => {
-            eprintln!("Encountered an error:\n{}", err);
-            drop(err);
+            let err = match err.downcast::<CompileError>() {
+                Ok(err) => *err,
+                Err(err) => CompileError::new(err.to_string()),
+            };
+            report(&options, &err);
             std::process::exit(1);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Makes an empty folder to run the translator in, unique to the test using it.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("translator-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn on_disk(path: PathBuf) -> SourceFile {
+        SourceFile {
+            path,
+            contents: None,
+            message: None,
+        }
+    }
+
+    /// A program that uses static variables 0 through `count - 1`.
+    fn program_with_statics(count: usize) -> VmProgram {
+        let source: String = (0..count)
+            .map(|index| format!("push static {}\n", index))
+            .collect();
+        let mut program = VmProgram::new();
+        parse::parse(&mut program, &source, "Test.vm", &Options::default()).unwrap();
+        program
+    }
+
+    #[test]
+    fn warns_when_static_memory_is_nearly_full() {
+        let options = Options::default();
+        let warning = check_static_size(&options, &program_with_statics(230)).unwrap();
+        let warning = warning.expect("230 statics should be close to the limit");
+        assert_eq!(warning.severity, Severity::Warning);
+        assert!(warning.message.contains("230 of the 240"));
+        let fine = check_static_size(&options, &program_with_statics(100)).unwrap();
+        assert!(fine.is_none());
+        assert!(check_static_size(&options, &program_with_statics(241)).is_err());
+    }
+
+    #[test]
+    fn colors_can_be_turned_on_and_off() {
+        let location = translator::error::Location {
+            file: "Test.vm".to_owned(),
+            line: 3,
+            col: 1,
+        };
+        let err = CompileError::at("Something is wrong.", location);
+        let with = |color| Options {
+            color,
+            ..Options::default()
+        };
+        let plain = format_diagnostic(&with(ColorChoice::Never), &err);
+        assert_eq!(
+            plain,
+            "Encountered an error:\nSomething is wrong.\nEncountered at Test.vm:3:1"
+        );
+        let colored = format_diagnostic(&with(ColorChoice::Always), &err);
+        assert!(colored.contains("\x1b[31mSomething is wrong.\x1b[0m"));
+        assert!(colored.contains("\x1b[36mTest.vm:3:1\x1b[0m"));
+    }
+
+    #[test]
+    fn counts_the_commands_of_each_file() {
+        let dir = temp_dir("verbose");
+        std::fs::write(dir.join("A.vm"), "push constant 1\npop temp 0\n").unwrap();
+        std::fs::write(dir.join("B.vm"), "push constant 1\nneg\npop temp 0\n").unwrap();
+        let options = Options::default();
+        let mut program = VmProgram::new();
+        let mut lines = Vec::new();
+        for path in vm_files_in(&dir).unwrap() {
+            let before = program.commands.len();
+            add_file(
+                &options,
+                &mut Profile::default(),
+                &mut program,
+                &on_disk(path),
+            )
+            .unwrap();
+            let added = program.commands.len() - before;
+            lines.push(describe_command_count(added, program.commands.len()));
+        }
+        assert_eq!(
+            lines,
+            vec![
+                "    2 commands (2 total so far)",
+                "    3 commands (5 total so far)"
+            ]
+        );
+    }
+
+    #[test]
+    fn folder_output_can_share_a_name_with_an_input() {
+        let dir = temp_dir("collision").join("SimpleAdd");
+        std::fs::create_dir(&dir).unwrap();
+        let input = dir.join("SimpleAdd.vm");
+        std::fs::write(&input, "push constant 7\npush constant 8\nadd\n").unwrap();
+        let options = Options {
+            source_path: dir.to_string_lossy().into_owned(),
+            ..Options::default()
+        };
+        entry(&options).unwrap();
+        let output = std::fs::read_to_string(dir.join("SimpleAdd.asm")).unwrap();
+        assert!(output.contains("// command: arithmetic"));
+        // Writing over an input is still not allowed.
+        let options = Options {
+            output: Some(input.to_string_lossy().into_owned()),
+            ..options
+        };
+        let err = entry(&options).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("would overwrite one of the input files"));
+        let source = std::fs::read_to_string(&input).unwrap();
+        assert_eq!(source, "push constant 7\npush constant 8\nadd\n");
+    }
+
+    #[test]
+    fn profile_has_every_phase() {
+        let dir = temp_dir("profile");
+        let path = dir.join("Main.vm");
+        std::fs::write(&path, "push constant 1\npop temp 0\n").unwrap();
+        let mut profile = Profile::default();
+        translate_per_file(&Options::default(), &mut profile, &[on_disk(path)]).unwrap();
+        let text = profile.to_string();
+        assert!(text.starts_with("Time taken:\n"));
+        for phase in &[
+            "Reading files",
+            "Parsing",
+            "Validation",
+            "Optimization",
+            "Translation",
+            "Writing output",
+        ] {
+            assert!(text.contains(&format!("\n    {}: ", phase)), "{}", text);
+        }
+        assert!(dir.join("Main.asm").is_file());
+    }
+
+    #[test]
+    fn per_file_writes_one_output_per_file() {
+        let dir = temp_dir("per-file");
+        std::fs::write(dir.join("A.vm"), "push static 0\npop static 1\n").unwrap();
+        std::fs::write(dir.join("B.vm"), "push static 0\npop temp 0\n").unwrap();
+        let options = Options {
+            source_path: dir.to_string_lossy().into_owned(),
+            per_file: true,
+            ..Options::default()
+        };
+        entry(&options).unwrap();
+        let a = std::fs::read_to_string(dir.join("A.asm")).unwrap();
+        let b = std::fs::read_to_string(dir.join("B.asm")).unwrap();
+        // Each file's static variables start from the beginning of the segment.
+        assert!(a.contains("@16\n") && a.contains("@17\n"));
+        assert!(b.contains("@16\n") && !b.contains("@17\n"));
+        let outputs = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path());
+        let outputs = outputs.filter(|path| path.extension() == Some("asm".as_ref()));
+        assert_eq!(outputs.count(), 2);
+    }
+
+    #[test]
+    fn max_static_lowers_the_limit() {
+        let program = program_with_statics(10);
+        let with = |max| Options {
+            max_static: Some(max),
+            ..Options::default()
+        };
+        assert!(check_static_size(&with(20), &program).unwrap().is_none());
+        let err = check_static_size(&with(5), &program).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The program uses 10 static variables, more than the limit of 5 set by --max-static."
+        );
+    }
+
+    #[test]
+    fn comments_only_has_a_line_per_command() {
+        let dir = temp_dir("comments-only");
+        let path = dir.join("Main.vm");
+        let source = "function Main.main 0\n// a comment\npush constant 1\n\nreturn\n";
+        std::fs::write(&path, source).unwrap();
+        let options = Options {
+            source_path: path.to_string_lossy().into_owned(),
+            emit_comments_only: true,
+            ..Options::default()
+        };
+        entry(&options).unwrap();
+        let output = std::fs::read_to_string(dir.join("Main.asm")).unwrap();
+        assert_eq!(
+            output,
+            "// command: label Main.main\n// command: function 0\n\
+            // command: push constant 1\n// command: return\n"
+        );
+    }
+
+    #[test]
+    fn crlf_output_has_no_bare_newlines() {
+        let dir = temp_dir("crlf");
+        let path = dir.join("Main.vm");
+        std::fs::write(&path, "function Main.main 0\npush constant 1\nreturn\n").unwrap();
+        let options = Options {
+            source_path: path.to_string_lossy().into_owned(),
+            line_ending: LineEnding::Crlf,
+            ..Options::default()
+        };
+        entry(&options).unwrap();
+        let output = std::fs::read_to_string(dir.join("Main.asm")).unwrap();
+        assert!(output.contains("\r\n"));
+        assert_eq!(output.matches('\n').count(), output.matches("\r\n").count());
+    }
+
+    #[test]
+    fn no_bootstrap_leaves_out_the_stack_setup() {
+        let dir = temp_dir("no-bootstrap").join("Prog");
+        std::fs::create_dir(&dir).unwrap();
+        let sys = "function Sys.init 0\ncall Main.main 0\nlabel HALT\ngoto HALT\n";
+        std::fs::write(dir.join("Sys.vm"), sys).unwrap();
+        std::fs::write(
+            dir.join("Main.vm"),
+            "function Main.main 0\npush constant 0\nreturn\n",
+        )
+        .unwrap();
+        let output = |options: &Options| {
+            entry(options).unwrap();
+            std::fs::read_to_string(dir.join("Prog.asm")).unwrap()
+        };
+        let options = Options {
+            source_path: dir.to_string_lossy().into_owned(),
+            require_entry: true,
+            ..Options::default()
+        };
+        assert!(output(&options).starts_with("// Bootstrap\n@256\n"));
+        let options = Options {
+            bootstrap: false,
+            ..options
+        };
+        assert!(!output(&options).contains("@256\n"));
+        // Without the bootstrap code there is nothing that has to call Sys.init.
+        std::fs::remove_file(dir.join("Sys.vm")).unwrap();
+        assert!(!output(&options).contains("Sys.init"));
+    }
+
+    #[test]
+    fn layout_matches_the_translated_code() {
+        assert_eq!(layout::STACK_MEMORY_SIZE, 1792);
+        assert_eq!(layout::STATIC_MEMORY_SIZE, 240);
+        let source = "function Sys.init 0\npush static 0\npop pointer 1\nlabel HALT\ngoto HALT\n";
+        let mut program = VmProgram::new();
+        parse::parse(&mut program, source, "Sys.vm", &Options::default()).unwrap();
+        let result = translate::translate(program, &Options::default()).unwrap();
+        assert!(result.starts_with(&format!("// Bootstrap\n@{}\n", layout::STACK_MEMORY_START)));
+        assert!(result.contains(&format!("@{}\n", layout::STATIC_MEMORY_START)));
+        assert!(result.contains(&format!("@{}\n", layout::THAT)));
+    }
+
+    #[test]
+    fn self_test_passes() {
+        let options = Options {
+            self_test: true,
+            ..Options::default()
+        };
+        entry(&options).unwrap();
+    }
+
+    #[test]
+    fn vm_extension_can_be_upper_case() {
+        let dir = temp_dir("upper-case");
+        std::fs::write(dir.join("Main.VM"), "push constant 1\n").unwrap();
+        std::fs::write(dir.join("Other.vm"), "push constant 2\n").unwrap();
+        std::fs::write(dir.join("Notes.txt"), "not code\n").unwrap();
+        let files = vm_files_in(&dir).unwrap();
+        let names: Vec<_> = files
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec!["Main.VM", "Other.vm"]);
+        let options = Options {
+            source_path: dir.join("Main.VM").to_string_lossy().into_owned(),
+            ..Options::default()
+        };
+        entry(&options).unwrap();
+        assert!(dir.join("Main.asm").is_file());
+    }
+
+    #[test]
+    fn only_assembly_has_instructions_to_count() {
+        let dir = temp_dir("count-normalized");
+        std::fs::write(dir.join("Main.vm"), "push constant 1\npop temp 0\n").unwrap();
+        let stats = dir.join("stats.json");
+        let counted_nothing = |emit_vm_normalized: bool| {
+            let options = Options {
+                source_path: dir.join("Main.vm").to_string_lossy().into_owned(),
+                stats_json: Some(stats.to_string_lossy().into_owned()),
+                emit_vm_normalized,
+                ..Options::default()
+            };
+            entry(&options).unwrap();
+            let json = std::fs::read_to_string(&stats).unwrap();
+            json.contains("\"instructions\":0,")
+        };
+        assert!(!counted_nothing(false));
+        assert!(counted_nothing(true));
+    }
+
+    #[test]
+    fn folders_without_a_name_use_their_absolute_path() {
+        let dir = temp_dir("no-name").join("Prog");
+        std::fs::create_dir_all(dir.join("Sub")).unwrap();
+        std::fs::write(dir.join("Main.vm"), "push constant 1\n").unwrap();
+        // Like `.`, this path ends in something other than a name.
+        let source_path = dir.join("Sub").join("..");
+        assert_eq!(source_path.file_name(), None);
+        let options = Options {
+            source_path: source_path.to_string_lossy().into_owned(),
+            ..Options::default()
+        };
+        entry(&options).unwrap();
+        assert!(dir.join("Prog.asm").is_file());
+    }
+
+    #[test]
+    fn exclude_wins_over_include_only() {
+        let dir = temp_dir("filters").join("Prog");
+        std::fs::create_dir(&dir).unwrap();
+        for name in &["Main", "MainTest", "Other"] {
+            let source = format!("function {}.f 0\npush constant 0\nreturn\n", name);
+            std::fs::write(dir.join(format!("{}.vm", name)), source).unwrap();
+        }
+        let options = Options {
+            source_path: dir.to_string_lossy().into_owned(),
+            include_only: vec!["Main*".to_owned()],
+            exclude: vec!["*Test.vm".to_owned()],
+            ..Options::default()
+        };
+        entry(&options).unwrap();
+        let output = std::fs::read_to_string(dir.join("Prog.asm")).unwrap();
+        assert!(output.contains("(Main.f)"));
+        assert!(!output.contains("(MainTest.f)"));
+        assert!(!output.contains("(Other.f)"));
+        let options = Options {
+            include_only: vec!["*Test.vm".to_owned()],
+            ..options
+        };
+        assert_eq!(entry(&options).unwrap_err().to_string(), NOTHING_INCLUDED);
+    }
+
+    #[test]
+    fn config_paths_are_relative_and_levels_are_replaced() {
+        let dir = temp_dir("config").join("Prog");
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("Main.vm"), "push constant 1\n").unwrap();
+        std::fs::write(
+            dir.join(options::CONFIG_FILE_NAME),
+            "optimize-level = 2\ndepfile = \"deps.d\"\nstats-json = \"stats.json\"\n",
+        )
+        .unwrap();
+        let path = dir.to_string_lossy().into_owned();
+        let options = parse_options(vec![path.clone()]).unwrap();
+        assert!(options.inline_functions && options.fold_constants);
+        let in_dir = |name: &str| Some(dir.join(name).to_string_lossy().into_owned());
+        assert_eq!(options.depfile, in_dir("deps.d"));
+        assert_eq!(options.stats_json, in_dir("stats.json"));
+        let options = parse_options(vec!["-O0".to_owned(), path.clone()]).unwrap();
+        assert_eq!(
+            (options.inline_functions, options.fold_constants),
+            (false, false)
+        );
+        // An empty path prints the statistics instead.
+        std::fs::write(dir.join(options::CONFIG_FILE_NAME), "stats-json = \"\"\n").unwrap();
+        let options = parse_options(vec![path]).unwrap();
+        assert_eq!(options.stats_json, Some(String::new()));
+    }
+
+    #[test]
+    fn depfile_lists_every_input() {
+        let dir = temp_dir("depfile").join("My Prog");
+        std::fs::create_dir(&dir).unwrap();
+        for name in &["Main", "Other"] {
+            let source = format!("function {}.f 0\npush constant 0\nreturn\n", name);
+            std::fs::write(dir.join(format!("{}.vm", name)), source).unwrap();
+        }
+        let depfile = dir.join("Prog.d");
+        let options = Options {
+            source_path: dir.to_string_lossy().into_owned(),
+            depfile: Some(depfile.to_string_lossy().into_owned()),
+            ..Options::default()
+        };
+        entry(&options).unwrap();
+        let escape = |path: PathBuf| path.to_string_lossy().replace(' ', "\\ ");
+        assert_eq!(
+            std::fs::read_to_string(&depfile).unwrap(),
+            format!(
+                "{}: \\\n  {} \\\n  {}\n",
+                escape(dir.join("My Prog.asm")),
+                escape(dir.join("Main.vm")),
+                escape(dir.join("Other.vm"))
+            )
+        );
+    }
+
+    #[test]
+    fn static_base_moves_the_first_static() {
+        let dir = temp_dir("static-base");
+        let input = dir.join("Main.vm");
+        std::fs::write(&input, "push static 0\npop static 1\n").unwrap();
+        let path = input.to_string_lossy().into_owned();
+        let args = vec!["--static-base".to_owned(), "100".to_owned(), path.clone()];
+        entry(&Options::from_args(args).unwrap()).unwrap();
+        let output = std::fs::read_to_string(dir.join("Main.asm")).unwrap();
+        assert!(output.contains("@116\n"));
+        assert!(output.contains("@117\n"));
+        assert!(!output.contains("@16\n"));
+        let args = vec!["--static-base=240".to_owned(), path];
+        assert_eq!(
+            Options::from_args(args).unwrap_err().to_string(),
+            "The option \"--static-base\" expects a slot in the static segment (at most 239), got \
+            240 instead."
+        );
+    }
+
+    #[test]
+    fn lists_every_function_with_its_locals() {
+        let dir = temp_dir("list-functions");
+        std::fs::write(
+            dir.join("Main.vm"),
+            "function Main.main 2\npush constant 0\nreturn\n\nfunction Main.helper 0\n\
+            push constant 1\nreturn\n",
+        )
+        .unwrap();
+        let files = vec![on_disk(dir.join("Main.vm"))];
+        let path = dir.join("Main.vm").to_string_lossy().into_owned();
+        let lines = function_list(&Options::default(), &files).unwrap();
+        assert_eq!(
+            lines,
+            vec![
+                format!("{}:1:1\tMain.main\t2", path),
+                format!("{}:5:1\tMain.helper\t0", path)
+            ]
+        );
+        let options = Options {
+            error_format: ErrorFormat::Json,
+            ..Options::default()
+        };
+        let lines = function_list(&options, &files).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("{\"name\":\"Main.helper\",\"num_locals\":0,"));
+    }
+
+    #[test]
+    fn programs_have_to_fit_in_rom() {
+        let dir = temp_dir("rom-size");
+        let input = dir.join("Main.vm");
+        let options = Options {
+            source_path: input.to_string_lossy().into_owned(),
+            check_rom_size: true,
+            ..Options::default()
+        };
+        std::fs::write(&input, "push constant 1\npop temp 0\n".repeat(3100)).unwrap();
+        let err = entry(&options).unwrap_err().to_string();
+        assert!(err.starts_with("The program is "), "{}", err);
+        assert!(err.ends_with(" that fit in ROM."), "{}", err);
+        std::fs::write(&input, "push constant 1\npop temp 0\n".repeat(100)).unwrap();
+        entry(&options).unwrap();
+        check_rom_size(&Options::default(), 32768).unwrap();
+        assert_eq!(
+            check_rom_size(&Options::default(), 32769)
+                .unwrap_err()
+                .to_string(),
+            "The program is 32769 instructions long, 1 more than the 32768 that fit in ROM."
+        );
+    }
+
+    #[test]
+    fn missing_files_keep_the_io_error() {
+        let path = temp_dir("missing").join("Missing.vm");
+        let err = read_file(&Options::default(), &path).unwrap_err();
+        assert!(err.to_string().starts_with("Failed to open \""));
+        let cause = err.source().unwrap();
+        let cause = cause.downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(cause.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn jack_os_preset_links_in_the_os() {
+        let dir = temp_dir("jack-os");
+        let (os, prog) = (dir.join("Os"), dir.join("Prog"));
+        std::fs::create_dir(&os).unwrap();
+        std::fs::create_dir(&prog).unwrap();
+        std::fs::write(
+            os.join("Sys.vm"),
+            "function Sys.init 0\ncall Main.main 0\nlabel HALT\ngoto HALT\n",
+        )
+        .unwrap();
+        let math = |body: &str| format!("function Math.abs 0\n{}\nreturn\n", body);
+        std::fs::write(os.join("Math.vm"), math("push constant 1")).unwrap();
+        std::fs::write(prog.join("Math.vm"), math("push constant 2")).unwrap();
+        std::fs::write(
+            prog.join("Main.vm"),
+            "function Main.main 0\ncall Math.abs 0\nreturn\n",
+        )
+        .unwrap();
+        let args = vec![
+            "--preset=jack-os".to_owned(),
+            format!("--os-dir={}", os.to_string_lossy()),
+            prog.to_string_lossy().into_owned(),
+        ];
+        let options = Options::from_args(args).unwrap();
+        assert!(options.require_entry);
+        entry(&options).unwrap();
+        let output = std::fs::read_to_string(prog.join("Prog.asm")).unwrap();
+        assert!(output.contains("(Sys.init)\n"));
+        // The program's own Math replaces the one from the OS.
+        assert_eq!(output.matches("(Math.abs)\n").count(), 1);
+        assert!(output.contains("// command: push Constant 2\n"));
+        assert!(!output.contains("// command: push Constant 1\n"));
+        // Without the OS there is no Sys.init for the bootstrap code to call.
+        std::fs::remove_file(os.join("Sys.vm")).unwrap();
+        assert!(entry(&options).is_err());
+    }
+
+    #[test]
+    fn per_file_outputs_define_different_labels() {
+        let dir = temp_dir("per-file-labels");
+        for name in &["Lib", "Main"] {
+            let source = format!(
+                "function {0}.f 0\npush constant 1\npush constant 2\nlt\ncall {0}.f 0\nreturn\n",
+                name
+            );
+            std::fs::write(dir.join(format!("{}.vm", name)), source).unwrap();
+        }
+        let options = Options {
+            source_path: dir.to_string_lossy().into_owned(),
+            per_file: true,
+            ..Options::default()
+        };
+        entry(&options).unwrap();
+        let labels = |name: &str| -> Vec<String> {
+            let output = std::fs::read_to_string(dir.join(name)).unwrap();
+            let labels = output.lines().filter(|line| line.starts_with("(__VM_IMPL"));
+            labels.map(str::to_owned).collect()
+        };
+        let (lib, main) = (labels("Lib.asm"), labels("Main.asm"));
+        assert_eq!(lib.len(), 2);
+        assert_eq!(main.len(), 2);
+        assert!(lib.iter().all(|label| !main.contains(label)));
+        assert!(lib[0].starts_with("(__VM_IMPL_LABEL_Lib.vm$"));
+    }
+
+    #[test]
+    fn per_file_output_does_not_share_comparisons() {
+        let dir = temp_dir("per-file-shared");
+        for name in &["Lib", "Main"] {
+            let source = format!(
+                "function {}.f 0\npush constant 1\npush constant 2\nlt\nreturn\n",
+                name
+            );
+            std::fs::write(dir.join(format!("{}.vm", name)), source).unwrap();
+        }
+        let path = dir.to_string_lossy().into_owned();
+        let args = |extra: &str| vec!["--per-file".to_owned(), extra.to_owned(), path.clone()];
+        entry(&Options::from_args(args("-O2")).unwrap()).unwrap();
+        for name in &["Lib.asm", "Main.asm"] {
+            let output = std::fs::read_to_string(dir.join(name)).unwrap();
+            assert!(!output.contains("__VM_IMPL_LABEL_COMPARE"), "{}", output);
+        }
+        let err = Options::from_args(args("--shared-comparisons")).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("--shared-comparisons can't be combined with --per-file"));
+    }
+
+    #[test]
+    fn per_file_keeps_functions_other_files_call() {
+        let dir = temp_dir("per-file-inline");
+        std::fs::write(
+            dir.join("Lib.vm"),
+            "function Lib.zero 0\npush constant 0\nreturn\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("Main.vm"), "call Lib.zero 0\n").unwrap();
+        let path = dir.to_string_lossy().into_owned();
+        for level in &["--inline", "-O2"] {
+            let args = vec!["--per-file".to_owned(), level.to_string(), path.clone()];
+            entry(&Options::from_args(args).unwrap()).unwrap();
+            let lib = std::fs::read_to_string(dir.join("Lib.asm")).unwrap();
+            assert!(lib.contains("(Lib.zero)\n"), "{}", level);
+            let main = std::fs::read_to_string(dir.join("Main.asm")).unwrap();
+            assert!(main.contains("@Lib.zero\n"), "{}", level);
+        }
+    }
+}
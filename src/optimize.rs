@@ -0,0 +1,382 @@
+//! Optional passes that make programs smaller without changing what they do. They all work on VM
+//! commands, before translation.
+
+use crate::{
+    options::Options,
+    vm_program::{ArithmeticOpcode, CommandInfo, MemorySegment, VmCommand, VmProgram},
+};
+use std::collections::{HashMap, HashSet};
+
+type Commands = Vec<(VmCommand, CommandInfo)>;
+type Pass = fn(&mut Commands, &Options);
+
+/// Functions with at most this many commands (not counting the return) can be inlined.
+const MAX_INLINED_COMMANDS: usize = 8;
+
+/// Runs every pass turned on in the options, returning the name of each one along with how much
+/// it changed the number of commands by. This is usually negative, but inlining can make a
+/// program longer while still making its output shorter.
+pub fn optimize(program: &mut VmProgram, options: &Options) -> Vec<(&'static str, isize)> {
+    let passes: [(&'static str, bool, Pass); 4] = [
+        ("inlining", options.inline_functions, inline_functions),
+        ("constant folding", options.fold_constants, fold_constants),
+        ("peephole", options.peephole, peephole),
+        (
+            "dead code elimination",
+            options.remove_dead_code,
+            remove_dead_code,
+        ),
+    ];
+    let mut commands: Commands = std::mem::take(&mut program.commands)
+        .into_iter()
+        .zip(std::mem::take(&mut program.info))
+        .collect();
+    let mut changes = Vec::new();
+    for (name, enabled, pass) in passes.iter() {
+        if *enabled {
+            let previous_len = commands.len();
+            pass(&mut commands, options);
+            changes.push((*name, commands.len() as isize - previous_len as isize));
+        }
+    }
+    let (commands, info) = commands.into_iter().unzip();
+    program.commands = commands;
+    program.info = info;
+    changes
+}
+
+/// What an arithmetic command would result in with constant operands, if the result is something
+/// `push constant` can make. Values are words of `Options::word_bits` bits, which are negative when
+/// their top bit is set, and results wrap around to fit in a word like they do when the program
+/// runs.
+fn evaluate(opcode: ArithmeticOpcode, a: usize, b: usize, options: &Options) -> Option<usize> {
+    use ArithmeticOpcode::*;
+    let word_size = options.true_value() as i32 + 1;
+    let signed = |value: usize| {
+        let value = value as i32 & (word_size - 1);
+        if value > options.max_constant() as i32 {
+            value - word_size
+        } else {
+            value
+        }
+    };
+    let (a, b) = (signed(a), signed(b));
+    let boolean = |value: bool| if value { -1 } else { 0 };
+    let result = match opcode {
+        Add => a + b,
+        Sub => a - b,
+        Neg => -a,
+        Eq => boolean(a == b),
+        Gt => boolean(a > b),
+        Lt => boolean(a < b),
+        And => a & b,
+        Or => a | b,
+        Not => !a,
+    };
+    let result = result.rem_euclid(word_size) as usize;
+    if result <= options.max_constant() {
+        Some(result)
+    } else {
+        None
+    }
+}
+
+fn is_unary(opcode: ArithmeticOpcode) -> bool {
+    matches!(opcode, ArithmeticOpcode::Neg | ArithmeticOpcode::Not)
+}
+
+/// Replaces arithmetic on constants with the constant it results in. Since the folded commands
+/// have to be right next to each other, there can't be a label between them that something else
+/// jumps to.
+fn fold_constants(commands: &mut Commands, options: &Options) {
+    let mut folded: Commands = Vec::with_capacity(commands.len());
+    for (command, info) in commands.drain(..) {
+        folded.push((command, info));
+        // Each fold can make a new constant to fold with the one before it.
+        loop {
+            let len = folded.len();
+            let opcode = match folded.last() {
+                Some((VmCommand::Arithmetic(opcode), _)) => *opcode,
+                _ => break,
+            };
+            let operands = if is_unary(opcode) { 1 } else { 2 };
+            if len < operands + 1 {
+                break;
+            }
+            let mut values = folded[len - 1 - operands..len - 1]
+                .iter()
+                .map(|(command, _)| match command {
+                    VmCommand::Push(MemorySegment::Constant, value) => Some(*value),
+                    _ => None,
+                });
+            let a = values.next().flatten();
+            let b = if operands == 2 {
+                values.next().flatten()
+            } else {
+                Some(0)
+            };
+            let result = match (a, b) {
+                (Some(a), Some(b)) => evaluate(opcode, a, b, options),
+                _ => None,
+            };
+            let result = match result {
+                Some(result) => result,
+                None => break,
+            };
+            folded.truncate(len - operands);
+            let (command, _) = folded.last_mut().unwrap();
+            *command = VmCommand::Push(MemorySegment::Constant, result);
+        }
+    }
+    *commands = folded;
+}
+
+/// Removes pairs of commands right next to each other that cancel out.
+fn peephole(commands: &mut Commands, _options: &Options) {
+    let mut result: Commands = Vec::with_capacity(commands.len());
+    for (command, info) in commands.drain(..) {
+        let cancels = match (result.last(), &command) {
+            (Some((VmCommand::Push(pushed, pushed_index), _)), VmCommand::Pop(popped, index)) => {
+                pushed == popped && pushed_index == index
+            }
+            (Some((VmCommand::Arithmetic(previous), _)), VmCommand::Arithmetic(opcode)) => {
+                previous == opcode && is_unary(*opcode)
+            }
+            _ => false,
+        };
+        if cancels {
+            result.pop();
+        } else {
+            result.push((command, info));
+        }
+    }
+    *commands = result;
+}
+
+/// Removes commands after a goto or return that can't be reached because no label comes before
+/// them. Raw assembly might contain labels, so that is kept too.
+fn remove_dead_code(commands: &mut Commands, _options: &Options) {
+    let mut reachable = true;
+    commands.retain(|(command, _)| {
+        if let VmCommand::Label(_) | VmCommand::RawAsm(_) = command {
+            reachable = true;
+        }
+        let keep = reachable;
+        if command.is_terminator() {
+            reachable = false;
+        }
+        keep
+    });
+}
+
+/// Whether a function's body (including its return) does the same thing pasted in place of a call
+/// to it. This is only true for small functions without arguments or locals that don't call
+/// anything, jump anywhere, or change `pointer`, since the caller's THIS and THAT are the same as
+/// the ones the function starts with. The body also has to leave exactly one value on the stack
+/// for the return value, without ever taking off more than it put on.
+fn is_inlinable(body: &[(VmCommand, CommandInfo)]) -> bool {
+    use MemorySegment::*;
+    let (last, body) = match body.split_last() {
+        Some(split) => split,
+        None => return false,
+    };
+    if last.0 != VmCommand::Return || body.len() > MAX_INLINED_COMMANDS {
+        return false;
+    }
+    let mut depth = 0;
+    for (command, _) in body {
+        let (needed, change) = match command {
+            VmCommand::Push(Argument, _) | VmCommand::Push(Local, _) => return false,
+            VmCommand::Push(..) => (0, 1),
+            VmCommand::Pop(Argument, _) | VmCommand::Pop(Local, _) | VmCommand::Pop(Pointer, _) => {
+                return false
+            }
+            VmCommand::Pop(..) => (1, -1),
+            VmCommand::Arithmetic(opcode) if is_unary(*opcode) => (1, 0),
+            VmCommand::Arithmetic(_) => (2, -1),
+            VmCommand::Nop => (0, 0),
+            _ => return false,
+        };
+        if depth < needed {
+            return false;
+        }
+        depth += change;
+    }
+    depth == 1
+}
+
+/// Replaces calls to tiny functions with their bodies, which saves the many instructions a call
+/// and return take. Functions that had calls replaced and end up never being called are removed,
+/// unless they are the entry function or raw assembly might refer to them. Nothing is removed if
+/// the program might not be whole, like with `--per-file` or `--no-bootstrap`, since code
+/// elsewhere could still call the function.
+fn inline_functions(commands: &mut Commands, options: &Options) {
+    let file = |index: usize| commands[index].1.position.as_ref().map(|pos| pos.file);
+    // Functions end where the next one starts, or along with their file.
+    let mut bodies: HashMap<&str, (usize, usize)> = HashMap::new();
+    for start in 0..commands.len().saturating_sub(1) {
+        let name = match (&commands[start].0, &commands[start + 1].0) {
+            (VmCommand::Label(name), VmCommand::FnSetup { num_locals: 0 }) => name,
+            _ => continue,
+        };
+        let mut end = start + 2;
+        while end < commands.len() && file(end) == file(start) {
+            if let (VmCommand::Label(_), Some((VmCommand::FnSetup { .. }, _))) =
+                (&commands[end].0, commands.get(end + 1))
+            {
+                break;
+            }
+            end += 1;
+        }
+        if is_inlinable(&commands[start + 2..end]) {
+            bodies.insert(name, (start, end));
+        }
+    }
+    if bodies.is_empty() {
+        return;
+    }
+
+    let mut still_used = vec![options.entry_function()];
+    let mut inlined = HashSet::new();
+    for (command, _) in commands.iter() {
+        match command {
+            VmCommand::Call {
+                fn_name,
+                num_args: 0,
+            } if bodies.contains_key(&fn_name[..]) => {
+                inlined.insert(&fn_name[..]);
+            }
+            VmCommand::Call { fn_name, .. } => still_used.push(fn_name),
+            VmCommand::RawAsm(code) => {
+                still_used.extend(bodies.keys().filter(|name| code.contains(*name)));
+            }
+            _ => (),
+        }
+    }
+    let whole_program = options.bootstrap && !options.per_file;
+    let mut unused: Vec<(usize, usize)> = bodies
+        .iter()
+        .filter(|(name, _)| whole_program && inlined.contains(*name) && !still_used.contains(name))
+        .map(|(_, range)| *range)
+        .collect();
+    unused.sort_unstable();
+
+    let mut result: Commands = Vec::with_capacity(commands.len());
+    let mut unused = unused.into_iter().peekable();
+    let mut index = 0;
+    while index < commands.len() {
+        if let Some((_, end)) = unused.next_if(|(start, _)| *start == index) {
+            index = end;
+            continue;
+        }
+        let (command, info) = &commands[index];
+        match command {
+            VmCommand::Call {
+                fn_name,
+                num_args: 0,
+            } if bodies.contains_key(&fn_name[..]) => {
+                let (start, end) = bodies[&fn_name[..]];
+                result.extend_from_slice(&commands[start + 2..end - 1]);
+            }
+            _ => result.push((command.clone(), info.clone())),
+        }
+        index += 1;
+    }
+    *commands = result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util::parse_program, translate};
+
+    #[test]
+    fn reports_what_each_pass_removed() {
+        let options = Options {
+            fold_constants: true,
+            peephole: true,
+            remove_dead_code: true,
+            ..Options::default()
+        };
+        let source = "push constant 2\npush constant 3\nadd\npop temp 0\n\
+            push local 0\npop local 0\ngoto END\npush constant 1\nlabel END\n";
+        let mut program = parse_program(source, &options);
+        let before = program.commands.len();
+        let changes = optimize(&mut program, &options);
+        assert_eq!(
+            changes,
+            vec![
+                ("constant folding", -2),
+                ("peephole", -2),
+                ("dead code elimination", -1)
+            ]
+        );
+        let total: isize = changes.iter().map(|(_, change)| change).sum();
+        assert_eq!(program.commands.len() as isize, before as isize + total);
+        assert_eq!(program.info.len(), program.commands.len());
+    }
+
+    #[test]
+    fn inlines_a_trivial_getter() {
+        let options = Options {
+            inline_functions: true,
+            ..Options::default()
+        };
+        let source = "function Sys.init 0\ncall Main.get 0\npop temp 0\nlabel HALT\n\
+            goto HALT\nfunction Main.get 0\npush this 0\nreturn\n";
+        let mut program = parse_program(source, &options);
+        optimize(&mut program, &options);
+        assert_eq!(
+            program.commands,
+            vec![
+                VmCommand::Label("Sys.init".to_owned()),
+                VmCommand::FnSetup { num_locals: 0 },
+                VmCommand::Push(MemorySegment::This, 0),
+                VmCommand::Pop(MemorySegment::Temp, 0),
+                VmCommand::Label("HALT".to_owned()),
+                VmCommand::Goto("HALT".to_owned()),
+            ]
+        );
+        let result = translate::translate(program, &options).unwrap();
+        assert!(!result.contains("Main.get"));
+    }
+
+    #[test]
+    fn only_removes_functions_whose_calls_were_inlined() {
+        let options = Options {
+            inline_functions: true,
+            ..Options::default()
+        };
+        let defined = |program: &VmProgram| -> Vec<String> {
+            let functions = program.functions().into_iter();
+            functions.filter_map(|function| function.name).collect()
+        };
+        // Nothing calls Main.zero, so it is left alone.
+        let source = "function Sys.init 0\nlabel HALT\ngoto HALT\n\
+            function Main.zero 0\npush constant 0\nreturn\n";
+        let mut program = parse_program(source, &options);
+        optimize(&mut program, &options);
+        assert_eq!(defined(&program), vec!["Sys.init", "Main.zero"]);
+        // Without the bootstrap, code that isn't part of this program might still call it.
+        let source = "function Sys.init 0\ncall Main.zero 0\npop temp 0\nlabel HALT\n\
+            goto HALT\nfunction Main.zero 0\npush constant 0\nreturn\n";
+        for (bootstrap, per_file, expected) in &[
+            (true, false, vec!["Sys.init"]),
+            (false, false, vec!["Sys.init", "Main.zero"]),
+            (true, true, vec!["Sys.init", "Main.zero"]),
+        ] {
+            let options = Options {
+                bootstrap: *bootstrap,
+                per_file: *per_file,
+                ..options.clone()
+            };
+            let mut program = parse_program(source, &options);
+            optimize(&mut program, &options);
+            assert_eq!(&defined(&program), expected);
+            assert!(!program
+                .commands
+                .iter()
+                .any(|command| matches!(command, VmCommand::Call { .. })));
+        }
+    }
+}
@@ -0,0 +1,836 @@
+use crate::{layout, vm_program::is_legal_identifier};
+use std::error::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// Readable messages with a footer pointing at the problem.
+    Human,
+    /// One JSON object per line, for editors and other tools.
+    Json,
+}
+
+/// Tweaks to the formatting of the output for assemblers and emulators that are picky about it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AssemblyDialect {
+    /// Whatever the translator produces normally.
+    Standard,
+    /// No comments or blank lines at all, only instructions and labels.
+    Strict,
+    /// Unconditional jumps are spelled `0;JMP`, and comments are only allowed on their own line.
+    Nand2TetrisWeb,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preset {
+    /// A whole Jack program: the OS is linked in and Sys.init must exist so the bootstrap code
+    /// has something to call.
+    JackOs,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Only use colors when printing to a terminal and NO_COLOR is not set.
+    Auto,
+    Always,
+    Never,
+}
+
+/// What goes at the end of each line of the output files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    /// What Windows normally uses.
+    Crlf,
+}
+
+/// Everything that can be configured from the command line.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// The file or folder to translate.
+    pub source_path: String,
+    pub error_format: ErrorFormat,
+    /// Emit a uniquely numbered comment before each command so emulator logs can be correlated
+    /// with the code that produced them.
+    pub trace_comments: bool,
+    /// Start the output with a comment listing which lines of it came from each file.
+    pub file_index: bool,
+    /// Put a blank line and a big comment with the function's name before the code of each
+    /// function.
+    pub section_per_function: bool,
+    /// What number the first column of a line is reported as in errors, either 0 or 1.
+    pub column_base: usize,
+    /// A character that starts a line comment, in addition to `//`.
+    pub comment_char: Option<char>,
+    /// Reject identifiers with characters outside of ASCII, even quoted ones.
+    pub ascii_only: bool,
+    /// Assemble the output internally to catch translator bugs that produce illegal assembly.
+    pub verify: bool,
+    /// Whether errors and warnings should be colorized.
+    pub color: ColorChoice,
+    /// Print a summary of how big the program is.
+    pub stats: bool,
+    /// Print the same summary as JSON, either to stdout (if `Some("")`) or to the given file.
+    pub stats_json: Option<String>,
+    /// Print the most values each function has on the stack at once.
+    pub stack_report: bool,
+    /// Warn about values left on the stack at a `goto` or `return`.
+    pub warn_unused_values: bool,
+    /// The function the bootstrap code calls, if it isn't Sys.init.
+    pub entry: Option<String>,
+    /// The most arguments a call or locals a function can have.
+    pub max_frame_size: usize,
+    /// How many `// @include` annotations deep files can include each other, so a file that
+    /// includes itself gives an error instead of running forever.
+    pub max_include_depth: usize,
+    /// Also write the assembled program as a .hack file.
+    pub emit_hack: bool,
+    /// Also write the assembled program as a Logisim memory image (.rom).
+    pub emit_logisim: bool,
+    /// Also write the assembled program as an Intel HEX file (.hex).
+    pub emit_ihex: bool,
+    /// Fail if the entry function isn't defined, instead of silently leaving out the bootstrap.
+    pub require_entry: bool,
+    /// A folder of .vm files (like the Jack OS) to include along with the source, except for
+    /// files that have the same name as one in the source.
+    pub os_dir: Option<String>,
+    /// Print more details about what is going on.
+    pub verbose: bool,
+    /// Print the symbols the parser splits the source into instead of translating it.
+    pub dump_tokens: bool,
+    /// Print every function the program defines instead of translating it.
+    pub list_functions: bool,
+    /// Instead of the assembly, output a `// command: ...` line for each command.
+    pub emit_comments_only: bool,
+    /// Instead of the assembly, output what each command does to the stack.
+    pub emit_stack_trace: bool,
+    /// Instead of the assembly, output the program as VM code written the same way throughout.
+    pub emit_vm_normalized: bool,
+    /// Check the translator against its built in programs instead of translating anything.
+    pub self_test: bool,
+    /// Where to write the translated program, instead of next to the source.
+    pub output: Option<String>,
+    /// Where to write a Makefile rule saying which files the output was made from.
+    pub depfile: Option<String>,
+    pub assembly_dialect: AssemblyDialect,
+    /// Print how long each phase of compilation took.
+    pub profile: bool,
+    /// Allow lines starting with `!`, which contain assembly to copy into the output.
+    pub allow_unknown_command: bool,
+    /// Translate each file into its own .asm file next to it.
+    pub per_file: bool,
+    /// Glob patterns for the files in a folder or archive to translate. Empty means all of them.
+    pub include_only: Vec<String>,
+    /// Glob patterns for files in a folder or archive to leave out, even if `include_only` matches
+    /// them.
+    pub exclude: Vec<String>,
+    /// Whether to put bootstrap code at the start when the entry function exists.
+    pub bootstrap: bool,
+    /// A lower limit on how many static variables can be used than what fits in memory.
+    pub max_static: Option<usize>,
+    /// How many slots at the start of the static segment to leave for other programs translated
+    /// separately, so the first static variable goes at address 16 plus this.
+    pub static_base: usize,
+    /// Replace calls to tiny functions with their bodies.
+    pub inline_functions: bool,
+    /// Replace arithmetic on constants with its result.
+    pub fold_constants: bool,
+    /// Remove pairs of commands that cancel out, like `push local 0` then `pop local 0`.
+    pub peephole: bool,
+    /// Remove commands that can never run.
+    pub remove_dead_code: bool,
+    /// Let calls and jumps refer to labels and functions with different capitalization.
+    pub case_insensitive: bool,
+    pub line_ending: LineEnding,
+    /// How many bits a word has, for experimenting with machines smaller than the Hack computer.
+    /// This changes the biggest constant, what true is, and where arithmetic wraps around.
+    pub word_bits: usize,
+    /// Check that there is something on the stack before taking things off of it while the
+    /// program runs, getting stuck in `__VM_TRAP` if not.
+    pub debug_asserts: bool,
+    /// The ROM address the output will be loaded at, which labels are offset by when assembling.
+    pub relocate: usize,
+    /// Fail if the output has more instructions than fit in ROM. This is always checked when the
+    /// output gets assembled, this turns it on for plain assembly too.
+    pub check_rom_size: bool,
+    /// Name the labels the translator makes up after the file and line of the command they are
+    /// for, instead of numbering them in order.
+    pub deterministic_labels: bool,
+    /// Make every comparison call one subroutine instead of each having its own code and label.
+    pub shared_comparisons: bool,
+    /// Translate a comparison followed by `if-goto` into a jump on the comparison itself, without
+    /// putting the result on the stack.
+    pub fuse_branches: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            source_path: String::new(),
+            error_format: ErrorFormat::Human,
+            trace_comments: false,
+            file_index: false,
+            column_base: 1,
+            comment_char: None,
+            section_per_function: false,
+            ascii_only: false,
+            verify: false,
+            color: ColorChoice::Auto,
+            stats: false,
+            stats_json: None,
+            stack_report: false,
+            warn_unused_values: false,
+            entry: None,
+            max_frame_size: layout::STACK_MEMORY_SIZE,
+            max_include_depth: 16,
+            emit_hack: false,
+            emit_logisim: false,
+            emit_ihex: false,
+            require_entry: false,
+            os_dir: None,
+            verbose: false,
+            dump_tokens: false,
+            list_functions: false,
+            emit_comments_only: false,
+            emit_stack_trace: false,
+            emit_vm_normalized: false,
+            self_test: false,
+            output: None,
+            depfile: None,
+            assembly_dialect: AssemblyDialect::Standard,
+            profile: false,
+            allow_unknown_command: false,
+            per_file: false,
+            include_only: Vec::new(),
+            exclude: Vec::new(),
+            bootstrap: true,
+            max_static: None,
+            static_base: 0,
+            inline_functions: false,
+            fold_constants: false,
+            peephole: false,
+            remove_dead_code: false,
+            case_insensitive: false,
+            line_ending: LineEnding::Lf,
+            word_bits: 16,
+            debug_asserts: false,
+            relocate: 0,
+            check_rom_size: false,
+            deterministic_labels: false,
+            shared_comparisons: false,
+            fuse_branches: false,
+        }
+    }
+}
+
+impl Options {
+    /// The biggest value `push constant` can make, the largest positive number in a word.
+    pub fn max_constant(&self) -> usize {
+        (1 << (self.word_bits - 1)) - 1
+    }
+
+    /// What true is stored as, every bit of a word set.
+    pub fn true_value(&self) -> usize {
+        (1 << self.word_bits) - 1
+    }
+
+    /// Whether a file found in a folder or archive should be translated, according to
+    /// `include_only` and `exclude`. Only the name of the file is matched, not its folders.
+    pub fn includes_file(&self, name: &str) -> bool {
+        let matches = |pattern: &String| glob_matches(pattern, name);
+        let included = self.include_only.is_empty() || self.include_only.iter().any(matches);
+        included && !self.exclude.iter().any(matches)
+    }
+
+    /// The function the bootstrap code should call.
+    pub fn entry_function(&self) -> &str {
+        self.entry.as_deref().unwrap_or("Sys.init")
+    }
+
+    /// Like `from_args`, but with options from a configuration file. They are given before the
+    /// arguments, so an option given both ways uses the value from the arguments. Since
+    /// optimization levels only turn things on, a level from the arguments replaces the one from
+    /// the configuration instead of being added to it.
+    pub fn from_args_and_config(
+        args: Vec<String>,
+        config: Vec<(String, Option<String>)>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let sets_level =
+            |arg: &String| arg.starts_with("-O") || arg.starts_with("--optimize-level");
+        let level_given = args.iter().any(sets_level);
+        let mut all_args = Vec::new();
+        for (name, value) in config {
+            if level_given && name == "optimize-level" {
+                continue;
+            }
+            all_args.push(match value {
+                Some(value) => format!("--{}={}", name, value),
+                None => format!("--{}", name),
+            });
+        }
+        all_args.extend(args);
+        Self::from_args(all_args)
+    }
+
+    /// Changes the defaults to the ones a preset calls for. `jack_os_dir` is where the jack-os
+    /// preset finds the OS unless `--os-dir` says otherwise.
+    fn apply_preset(&mut self, preset: Preset, jack_os_dir: Option<String>) {
+        match preset {
+            Preset::JackOs => {
+                self.require_entry = true;
+                self.os_dir = jack_os_dir;
+            }
+        }
+    }
+
+    /// Turns on the optimizations for a level given with `-O`. Each level includes everything the
+    /// ones before it do, and since this only ever turns things on, the order it is given in
+    /// relative to the individual options doesn't matter.
+    fn apply_optimize_level(&mut self, level: usize) -> Result<(), String> {
+        if level > 2 {
+            return Err(format!(
+                "Unknown optimization level {}, expected one of:\n0, 1, 2.",
+                level
+            ));
+        }
+        if level >= 1 {
+            self.fold_constants = true;
+            self.peephole = true;
+            self.remove_dead_code = true;
+            self.fuse_branches = true;
+        }
+        if level >= 2 {
+            self.inline_functions = true;
+            self.shared_comparisons = true;
+        }
+        Ok(())
+    }
+
+    /// Parses command line arguments, not including the name of the executable. Options can be
+    /// given as `--name=value` or `--name value`.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, Box<dyn Error>> {
+        Self::from_args_with_os_dir(args, std::env::var("JACK_OS_DIR").ok())
+    }
+
+    /// Like `from_args`, but with the folder the jack-os preset uses given directly instead of
+    /// coming from the `JACK_OS_DIR` environment variable.
+    fn from_args_with_os_dir(
+        args: impl IntoIterator<Item = String>,
+        jack_os_dir: Option<String>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut options = Self::default();
+        let mut source_path = None;
+        let args: Vec<_> = args.into_iter().collect();
+        // The preset is applied before anything else so that explicit options always win, no
+        // matter which order they were given in.
+        let mut preset = None;
+        for (index, arg) in args.iter().enumerate() {
+            let name = if let Some(name) = arg.strip_prefix("--preset=") {
+                name
+            } else if arg == "--preset" {
+                args.get(index + 1).map(|name| &name[..]).unwrap_or("")
+            } else {
+                continue;
+            };
+            preset = Some(match name {
+                "jack-os" => Preset::JackOs,
+                other => Err(format!(
+                    "Unknown preset \"{}\", expected one of:\njack-os.",
+                    other
+                ))?,
+            });
+        }
+        if let Some(preset) = preset {
+            options.apply_preset(preset, jack_os_dir);
+        }
+        // Unlike when -O2 turns it on, asking for this on its own with --per-file is an error.
+        let mut shared_comparisons_given = false;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // The only short options, since they are so common.
+            let arg = if arg == "-o" {
+                "--output".to_owned()
+            } else if let Some(level) = arg.strip_prefix("-O") {
+                format!("--optimize-level={}", level)
+            } else {
+                arg
+            };
+            if !arg.starts_with("--") {
+                if source_path.is_some() {
+                    Err(format!("Unexpected extra argument \"{}\".", arg))?;
+                }
+                source_path = Some(arg);
+                continue;
+            }
+            let (name, inline_value) = match arg.find('=') {
+                Some(index) => (&arg[..index], Some(arg[index + 1..].to_owned())),
+                None => (&arg[..], None),
+            };
+            // Grabs the value of an option that requires one.
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("The option \"{}\" requires a value.", name))
+            };
+            match name {
+                "--error-format" => {
+                    options.error_format = match &value()?[..] {
+                        "human" => ErrorFormat::Human,
+                        "json" => ErrorFormat::Json,
+                        other => Err(format!(
+                            "Unknown error format \"{}\", expected one of:\nhuman, json.",
+                            other
+                        ))?,
+                    }
+                }
+                "--trace-comments" => {
+                    no_value(name, &inline_value)?;
+                    options.trace_comments = true;
+                }
+                "--columns" => {
+                    options.column_base = match &value()?[..] {
+                        "0" => 0,
+                        "1" => 1,
+                        other => Err(format!(
+                            "Unknown column base \"{}\", expected one of:\n0, 1.",
+                            other
+                        ))?,
+                    }
+                }
+                "--file-index" => {
+                    no_value(name, &inline_value)?;
+                    options.file_index = true;
+                }
+                "--section-per-function" => {
+                    no_value(name, &inline_value)?;
+                    options.section_per_function = true;
+                }
+                "--comment-char" => {
+                    options.comment_char = match &value()?[..] {
+                        "//" => None,
+                        ";" => Some(';'),
+                        other => Err(format!(
+                            "Unknown comment character \"{}\", expected one of:\n//, ;.",
+                            other
+                        ))?,
+                    }
+                }
+                "--ascii-only" => {
+                    no_value(name, &inline_value)?;
+                    options.ascii_only = true;
+                }
+                "--verify" => {
+                    no_value(name, &inline_value)?;
+                    options.verify = true;
+                }
+                "--color" => {
+                    options.color = match &value()?[..] {
+                        "auto" => ColorChoice::Auto,
+                        "always" => ColorChoice::Always,
+                        "never" => ColorChoice::Never,
+                        other => Err(format!(
+                            "Unknown color choice \"{}\", expected one of:\nauto, always, never.",
+                            other
+                        ))?,
+                    }
+                }
+                "--debug-asserts" => {
+                    no_value(name, &inline_value)?;
+                    options.debug_asserts = true;
+                }
+                "--word-bits" => {
+                    options.word_bits = parse_number(name, &value()?)?;
+                    if !(2..=16).contains(&options.word_bits) {
+                        Err(format!(
+                            "The option \"--word-bits\" expects a number from 2 to 16, since the \
+                            output still runs on the 16 bit Hack computer, got {} instead.",
+                            options.word_bits
+                        ))?;
+                    }
+                }
+                "--line-endings" => {
+                    options.line_ending = match &value()?[..] {
+                        "lf" => LineEnding::Lf,
+                        "crlf" => LineEnding::Crlf,
+                        other => Err(format!(
+                            "Unknown line ending \"{}\", expected one of:\nlf, crlf.",
+                            other
+                        ))?,
+                    }
+                }
+                "--stats" => {
+                    no_value(name, &inline_value)?;
+                    options.stats = true;
+                }
+                "--stack-report" => {
+                    no_value(name, &inline_value)?;
+                    options.stack_report = true;
+                }
+                "--warn-unused-values" => {
+                    no_value(name, &inline_value)?;
+                    options.warn_unused_values = true;
+                }
+                // The value is optional here, so it can only be given with an equals sign.
+                "--stats-json" => options.stats_json = Some(inline_value.unwrap_or_default()),
+                "--entry" => {
+                    let entry = value()?;
+                    if !is_legal_identifier(&entry) {
+                        Err(format!(
+                            "The entry function \"{}\" is not a legal identifier.",
+                            entry
+                        ))?;
+                    }
+                    options.entry = Some(entry);
+                }
+                "--relocate" => {
+                    options.relocate = parse_number(name, &value()?)?;
+                    if options.relocate > 32767 {
+                        Err(format!(
+                            "The option \"--relocate\" expects an address in ROM (at most \
+                            32767), got {} instead.",
+                            options.relocate
+                        ))?;
+                    }
+                }
+                "--check-rom-size" => {
+                    no_value(name, &inline_value)?;
+                    options.check_rom_size = true;
+                }
+                "--max-static" => options.max_static = Some(parse_number(name, &value()?)?),
+                "--static-base" => {
+                    options.static_base = parse_number(name, &value()?)?;
+                    if options.static_base >= layout::STATIC_MEMORY_SIZE {
+                        Err(format!(
+                            "The option \"--static-base\" expects a slot in the static segment \
+                            (at most {}), got {} instead.",
+                            layout::STATIC_MEMORY_SIZE - 1,
+                            options.static_base
+                        ))?;
+                    }
+                }
+                "--max-frame-size" => options.max_frame_size = parse_number(name, &value()?)?,
+                "--max-include-depth" => {
+                    options.max_include_depth = parse_number(name, &value()?)?
+                }
+                "--emit-hack" => {
+                    no_value(name, &inline_value)?;
+                    options.emit_hack = true;
+                }
+                "--emit-logisim" => {
+                    no_value(name, &inline_value)?;
+                    options.emit_logisim = true;
+                }
+                "--emit-ihex" => {
+                    no_value(name, &inline_value)?;
+                    options.emit_ihex = true;
+                }
+                "--require-entry" => {
+                    no_value(name, &inline_value)?;
+                    options.require_entry = true;
+                }
+                "--no-bootstrap" => {
+                    no_value(name, &inline_value)?;
+                    options.bootstrap = false;
+                }
+                "--no-require-entry" => {
+                    no_value(name, &inline_value)?;
+                    options.require_entry = false;
+                }
+                "--verbose" => {
+                    no_value(name, &inline_value)?;
+                    options.verbose = true;
+                }
+                "--dump-tokens" => {
+                    no_value(name, &inline_value)?;
+                    options.dump_tokens = true;
+                }
+                "--list-functions" => {
+                    no_value(name, &inline_value)?;
+                    options.list_functions = true;
+                }
+                "--emit-comments-only" => {
+                    no_value(name, &inline_value)?;
+                    options.emit_comments_only = true;
+                }
+                "--emit-stack-trace" => {
+                    no_value(name, &inline_value)?;
+                    options.emit_stack_trace = true;
+                }
+                "--emit-vm-normalized" => {
+                    no_value(name, &inline_value)?;
+                    options.emit_vm_normalized = true;
+                }
+                "--assembly-dialect" => {
+                    options.assembly_dialect = match &value()?[..] {
+                        "standard" => AssemblyDialect::Standard,
+                        "strict" => AssemblyDialect::Strict,
+                        "nand2tetris-web" => AssemblyDialect::Nand2TetrisWeb,
+                        other => Err(format!(
+                            "Unknown assembly dialect \"{}\", expected one of:\nstandard, strict, nand2tetris-web.",
+                            other
+                        ))?,
+                    }
+                }
+                "--profile" => {
+                    no_value(name, &inline_value)?;
+                    options.profile = true;
+                }
+                "--allow-unknown-command" => {
+                    no_value(name, &inline_value)?;
+                    options.allow_unknown_command = true;
+                }
+                "--per-file" => {
+                    no_value(name, &inline_value)?;
+                    options.per_file = true;
+                }
+                "--optimize-level" => {
+                    options.apply_optimize_level(parse_number(name, &value()?)?)?
+                }
+                "--inline" => {
+                    no_value(name, &inline_value)?;
+                    options.inline_functions = true;
+                }
+                "--fold-constants" => {
+                    no_value(name, &inline_value)?;
+                    options.fold_constants = true;
+                }
+                "--peephole" => {
+                    no_value(name, &inline_value)?;
+                    options.peephole = true;
+                }
+                "--remove-dead-code" => {
+                    no_value(name, &inline_value)?;
+                    options.remove_dead_code = true;
+                }
+                "--case-insensitive" => {
+                    no_value(name, &inline_value)?;
+                    options.case_insensitive = true;
+                }
+                "--deterministic-labels" => {
+                    no_value(name, &inline_value)?;
+                    options.deterministic_labels = true;
+                }
+                "--shared-comparisons" => {
+                    no_value(name, &inline_value)?;
+                    options.shared_comparisons = true;
+                    shared_comparisons_given = true;
+                }
+                "--fuse-branches" => {
+                    no_value(name, &inline_value)?;
+                    options.fuse_branches = true;
+                }
+                "--self-test" => {
+                    no_value(name, &inline_value)?;
+                    options.self_test = true;
+                }
+                "--include-only" => options.include_only.push(value()?),
+                "--exclude" => options.exclude.push(value()?),
+                "--output" => options.output = Some(value()?),
+                "--depfile" => options.depfile = Some(value()?),
+                "--os-dir" => options.os_dir = Some(value()?),
+                // Already handled above.
+                "--preset" => {
+                    value()?;
+                }
+                _ => Err(format!("Unknown option \"{}\".", name))?,
+            }
+        }
+        options.source_path = match source_path {
+            Some(source_path) => source_path,
+            None if options.self_test => String::new(),
+            None => Err("Must specify a file or folder.")?,
+        };
+        if shared_comparisons_given && options.per_file {
+            Err(
+                "--shared-comparisons can't be combined with --per-file, since every file would \
+                define the same subroutine and return to addresses in itself.",
+            )?;
+        }
+        if preset == Some(Preset::JackOs) && options.os_dir.is_none() {
+            Err(
+                "The jack-os preset needs to know where the OS is, either from --os-dir or the \
+                JACK_OS_DIR environment variable.",
+            )?;
+        }
+        Ok(options)
+    }
+}
+
+/// Whether a name matches a pattern where `*` stands for any number of characters and `?` for
+/// exactly one.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Whether the pattern up to each index matches the name so far.
+    let mut matched = vec![false; pattern.len() + 1];
+    matched[0] = true;
+    for index in 0..pattern.len() {
+        matched[index + 1] = matched[index] && pattern[index] == '*';
+    }
+    for c in name {
+        let mut next = vec![false; pattern.len() + 1];
+        for index in 0..pattern.len() {
+            next[index + 1] = match pattern[index] {
+                '*' => matched[index + 1] || next[index],
+                '?' => matched[index],
+                other => matched[index] && other == c,
+            };
+        }
+        matched = next;
+    }
+    matched[pattern.len()]
+}
+
+/// The name of the project configuration file looked for next to the source.
+pub const CONFIG_FILE_NAME: &str = ".vmrc";
+
+/// Reads the options in a configuration file, which is written in a small part of TOML: one
+/// `name = value` per line, where the name is an option without the `--` in front and the value
+/// is a string, a nonnegative integer, or a boolean. Underscores in names count as dashes. Options
+/// come back in order along with their values, None for switches. Switches set to false are left
+/// out.
+pub fn parse_config(source: &str) -> Result<Vec<(String, Option<String>)>, String> {
+    let mut options = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let error =
+            |problem: &str| format!("Line {} of {}: {}", index + 1, CONFIG_FILE_NAME, problem);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(error(
+                "tables are not supported, every option goes at the top level.",
+            ));
+        }
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected \"name = value\"."))?;
+        let name = name.trim().replace('_', "-");
+        let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '-';
+        if name.is_empty() || !name.chars().all(is_name_char) {
+            return Err(error(&format!("\"{}\" is not an option name.", name)));
+        }
+        let value = parse_config_value(value.trim()).map_err(|problem| error(&problem))?;
+        match value {
+            Some(ConfigValue::Switch(true)) => options.push((name, None)),
+            Some(ConfigValue::Switch(false)) => (),
+            Some(ConfigValue::Text(value)) => options.push((name, Some(value))),
+            None => return Err(error("expected a string, an integer, true, or false.")),
+        }
+    }
+    Ok(options)
+}
+
+enum ConfigValue {
+    Switch(bool),
+    Text(String),
+}
+
+/// Parses the value of an option in a configuration file, along with any comment after it.
+/// Returns None if it isn't any kind of value.
+fn parse_config_value(value: &str) -> Result<Option<ConfigValue>, String> {
+    let (text, rest) = if let Some(literal) = value.strip_prefix('\'') {
+        let end = literal
+            .find('\'')
+            .ok_or("the string is missing its closing quote.")?;
+        (literal[..end].to_owned(), &literal[end + 1..])
+    } else if let Some(quoted) = value.strip_prefix('"') {
+        let mut text = String::new();
+        let mut chars = quoted.char_indices();
+        let end = loop {
+            match chars.next() {
+                Some((index, '"')) => break index,
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, escaped)) if escaped == '"' || escaped == '\\' => text.push(escaped),
+                    _ => return Err("only \\\" and \\\\ can be used in strings.".to_owned()),
+                },
+                Some((_, other)) => text.push(other),
+                None => return Err("the string is missing its closing quote.".to_owned()),
+            }
+        };
+        (text, &quoted[end + 1..])
+    } else {
+        let word = value.split('#').next().unwrap_or_default().trim();
+        return Ok(match word {
+            "true" => Some(ConfigValue::Switch(true)),
+            "false" => Some(ConfigValue::Switch(false)),
+            _ if !word.is_empty() && word.chars().all(|c| c.is_ascii_digit()) => {
+                Some(ConfigValue::Text(word.to_owned()))
+            }
+            _ => None,
+        });
+    };
+    let rest = rest.trim();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Err(format!("unexpected \"{}\" after the string.", rest));
+    }
+    Ok(Some(ConfigValue::Text(text)))
+}
+
+/// Complains if an option that is just a switch was given a value anyway.
+fn no_value(name: &str, inline_value: &Option<String>) -> Result<(), String> {
+    match inline_value {
+        Some(_) => Err(format!("The option \"{}\" does not take a value.", name)),
+        None => Ok(()),
+    }
+}
+
+fn parse_number(name: &str, value: &str) -> Result<usize, String> {
+    value.parse().map_err(|_| {
+        format!(
+            "The option \"{}\" expects a nonnegative integer, got \"{}\" instead.",
+            name, value
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_args(args: &[&str]) -> Result<Options, Box<dyn Error>> {
+        Options::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn entry_must_be_an_identifier() {
+        let options = from_args(&["--entry", "Main.start", "Prog"]).unwrap();
+        assert_eq!(options.entry_function(), "Main.start");
+        assert_eq!(Options::default().entry_function(), "Sys.init");
+        let err = from_args(&["--entry=Main-start", "Prog"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The entry function \"Main-start\" is not a legal identifier."
+        );
+    }
+
+    #[test]
+    fn jack_os_preset_can_be_overridden() {
+        let with_os_dir = |args: &[&str], os_dir: Option<&str>| {
+            let args = args.iter().map(|arg| arg.to_string());
+            Options::from_args_with_os_dir(args, os_dir.map(str::to_owned))
+        };
+        let options = with_os_dir(&["Prog", "--preset", "jack-os"], Some("/usr/share/jack-os"));
+        let options = options.unwrap();
+        assert!(options.require_entry);
+        assert_eq!(options.os_dir.as_deref(), Some("/usr/share/jack-os"));
+        let args = [
+            "--os-dir=MyOs",
+            "--no-require-entry",
+            "--preset=jack-os",
+            "Prog",
+        ];
+        let options = with_os_dir(&args, None).unwrap();
+        assert!(!options.require_entry);
+        assert_eq!(options.os_dir.as_deref(), Some("MyOs"));
+        // Without anywhere to find the OS, the preset can't do what it says.
+        let err = with_os_dir(&["--preset=jack-os", "Prog"], None).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("The jack-os preset needs to know where the OS is"));
+        let options = with_os_dir(&["Prog"], None).unwrap();
+        assert!(!options.require_entry);
+        assert_eq!(options.os_dir, None);
+    }
+}
@@ -1,43 +1,122 @@
-use crate::vm_program::{ArithmeticOpcode, CommandName, MemorySegment, VmCommand, VmProgram};
-use std::error::Error;
+use crate::{
+    error::{CompileError, Location},
+    options::Options,
+    vm_program::{
+        is_identifier_char, mangle_identifier, sanitize_identifier, CommandInfo, CommandName,
+        MemorySegment, SourcePosition, VmCommand, VmProgram,
+    },
+};
+use std::{error::Error, path::Path};
 
+/// Parses one command at a time. The only thing that nests is `// @include`, which starts another
+/// parser for the included file, so how deep that goes is limited by `Options::max_include_depth`.
 struct Parser<'a> {
     source: &'a str,
-    file_path: &'a str,
     current_line: usize,
     current_col: usize,
     /// Where (push static 0) and (pop static 0) should go.
     static_base: usize,
+    /// Index of this file in the output's list of files.
+    file_index: usize,
+    /// Where the command currently being parsed starts.
+    command_pos: SavedPosition,
+    /// The contents of the last `// @src` annotation, which is attached to the next command.
+    source_annotation: Option<String>,
+    /// The names from the last `// @locals` annotation, which are attached to the next function.
+    local_names: Option<Vec<String>>,
+    /// Paths from `// @include` annotations that haven't been parsed yet, along with where the
+    /// annotation is.
+    includes: Vec<(SavedPosition, String)>,
+    /// How many files deep in includes this file is, 0 if nothing included it.
+    include_depth: usize,
+    /// For an included file, the file the includes started from. See `CommandInfo::included_in`.
+    included_in: Option<usize>,
     output: &'a mut VmProgram,
+    options: &'a Options,
 }
 
 /* CONSTRUCTOR */
 
 impl<'a> Parser<'a> {
-    fn new(output: &'a mut VmProgram, source: &'a str, file_path: &'a str) -> Self {
+    fn new(
+        output: &'a mut VmProgram,
+        source: &'a str,
+        file_path: &'a str,
+        options: &'a Options,
+    ) -> Self {
+        let file_index = output.add_file(file_path);
+        Self::continuing(output, source, file_index, options)
+    }
+
+    /// Makes a parser for more of a file that is already part of the output.
+    fn continuing(
+        output: &'a mut VmProgram,
+        source: &'a str,
+        file_index: usize,
+        options: &'a Options,
+    ) -> Self {
         Self {
             source,
-            file_path,
             current_line: 1,
             current_col: 1,
-            // Our static variables should go after any other static variables in the program.
-            static_base: output.static_size,
+            static_base: output.file_static_bases[file_index],
+            file_index,
+            command_pos: (1, 1),
+            source_annotation: None,
+            local_names: None,
+            includes: Vec::new(),
+            include_depth: 0,
+            included_in: None,
             output,
+            options,
         }
     }
 }
 
+/// Not a real segment, `push shared Name 0` refers to a static variable that every file
+/// mentioning `Name` shares.
+const SHARED_SEGMENT: &str = "shared";
+
 /* ERROR HANDLING */
 
 type SavedPosition = (usize, usize);
 
+/// Says which command an error in its arguments happened in, like `While parsing the arguments to
+/// "call", expected ...`.
+fn in_arguments_to(err: Box<dyn Error>, command: CommandName) -> Box<dyn Error> {
+    let (mut err, name) = match (err.downcast::<CompileError>(), command.name()) {
+        (Ok(err), Some(name)) => (err, name),
+        (Ok(err), None) => return err,
+        (Err(err), _) => return err,
+    };
+    let mut chars = err.message.chars();
+    let first: String = chars
+        .next()
+        .into_iter()
+        .flat_map(char::to_lowercase)
+        .collect();
+    err.message = format!(
+        "While parsing the arguments to \"{}\", {}{}",
+        name,
+        first,
+        chars.as_str()
+    );
+    err
+}
+
 impl<'a> Parser<'a> {
     fn save_pos(&self) -> SavedPosition {
         (self.current_line, self.current_col)
     }
 
-    fn error_footer(&self, pos: SavedPosition) -> String {
-        format!("\nEncountered at {}:{}:{}", self.file_path, pos.0, pos.1,)
+    fn error_at(&self, pos: SavedPosition, message: String) -> Box<dyn Error> {
+        let location = Location {
+            file: self.output.files[self.file_index].clone(),
+            line: pos.0,
+            // Columns are tracked starting from 1 internally.
+            col: pos.1 - 1 + self.options.column_base,
+        };
+        CompileError::at(message, location).into()
     }
 
     fn expected_one_of_error_message<'i, T>(
@@ -53,12 +132,8 @@ impl<'a> Parser<'a> {
             .map(|s| s.to_owned())
             .collect::<Vec<_>>()
             .join(", ");
-        let footer = self.error_footer(pos);
-        let msg = format!(
-            "{}, expected one of:\n{}.{}",
-            problem, expected_desc, footer
-        );
-        msg.into()
+        let msg = format!("{}, expected one of:\n{}.", problem, expected_desc);
+        self.error_at(pos, msg)
     }
 
     fn expected_one_of_found_error_message<'i, T>(
@@ -87,13 +162,45 @@ impl<'a> Parser<'a> {
 pub type ParseResult<T = ()> = Result<T, Box<dyn Error>>;
 
 impl<'a> Parser<'a> {
+    /// Adds a command to the output, remembering that it came from the current command.
+    fn push_command(&mut self, command: VmCommand) {
+        let position = SourcePosition {
+            file: self.file_index,
+            line: self.command_pos.0,
+            col: self.command_pos.1,
+        };
+        let info = CommandInfo {
+            position: Some(position),
+            source_annotation: self.source_annotation.take(),
+            local_names: None,
+            included_in: self.included_in,
+        };
+        // The annotation only applies to a function right after it.
+        let local_names = self.local_names.take();
+        let info = match command {
+            VmCommand::FnSetup { .. } => CommandInfo {
+                local_names,
+                ..info
+            },
+            VmCommand::Label(..) => {
+                self.local_names = local_names;
+                info
+            }
+            _ => info,
+        };
+        self.output.push_command_with_info(command, info);
+    }
+
     fn peek(&self) -> Option<char> {
         self.source.chars().next()
     }
 
-    /// Panics if at the end of the file.
+    /// Does nothing at the end of the file, so that malformed input can't make the parser panic.
     fn advance(&mut self) {
-        let next = self.peek().unwrap();
+        let next = match self.peek() {
+            Some(next) => next,
+            None => return,
+        };
         // Not all characters take 1 byte.
         self.source = &self.source[next.len_utf8()..];
         if next == '\n' {
@@ -104,9 +211,9 @@ impl<'a> Parser<'a> {
         }
     }
 
-    /// Grabs the next symbol (contiguous group of characters without whitespace) and advances the
-    /// internal pointer beyond that point.
-    fn advance_symbol(&mut self) -> Option<(SavedPosition, &str)> {
+    /// Advances the internal pointer up to the next character that isn't whitespace or part of a
+    /// comment. Comments start with `//` or `Options::comment_char`.
+    fn skip_whitespace(&mut self) {
         let mut comment = false;
         while let Some(peeked) = self.peek() {
             if comment {
@@ -116,10 +223,15 @@ impl<'a> Parser<'a> {
                 self.advance()
             } else if peeked.is_whitespace() {
                 self.advance()
+            } else if Some(peeked) == self.options.comment_char {
+                self.advance();
+                self.parse_annotation();
+                comment = true;
             } else if peeked == '/' {
-                if self.source.chars().skip(1).next() == Some('/') {
+                if self.source.chars().nth(1) == Some('/') {
                     self.advance();
                     self.advance();
+                    self.parse_annotation();
                     comment = true;
                 } else {
                     // Just a single slash, not a comment. This is not valid syntax but acting
@@ -131,14 +243,70 @@ impl<'a> Parser<'a> {
                 break;
             }
         }
+    }
+
+    /// Checks if the comment we are in the middle of is an annotation like `// @src Foo.jack:42`,
+    /// `// @locals x y` or `// @include Util.vm`, saving its contents if so. Includes are only
+    /// recorded here and parsed once the comment is over. Annotations we don't know about are
+    /// ignored like any other comment.
+    fn parse_annotation(&mut self) {
+        let line_end = self.source.find('\n').unwrap_or(self.source.len());
+        let comment = self.source[..line_end].trim();
+        if let Some(source) = comment.strip_prefix("@src ") {
+            self.source_annotation = Some(source.trim().to_owned());
+        } else if let Some(names) = comment.strip_prefix("@locals ") {
+            self.local_names = Some(names.split_whitespace().map(str::to_owned).collect());
+        } else if let Some(path) = comment.strip_prefix("@include ") {
+            let indent = self.source[..line_end].len() - self.source[..line_end].trim_start().len();
+            let pos = (self.current_line, self.current_col + indent);
+            self.includes.push((pos, path.trim().to_owned()));
+        }
+    }
+
+    /// Parses the files from any `// @include` annotations since the last command, putting their
+    /// commands here. Included files use the static variables of the file including them.
+    fn parse_includes(&mut self) -> ParseResult {
+        for (pos, path) in std::mem::take(&mut self.includes) {
+            if self.include_depth >= self.options.max_include_depth {
+                let message = format!(
+                    "Files are included more than {} deep (set by --max-include-depth), which \
+                    usually means a file includes itself.",
+                    self.options.max_include_depth
+                );
+                return Err(self.error_at(pos, message));
+            }
+            let folder = Path::new(&self.output.files[self.file_index]).parent();
+            let full_path = folder.unwrap_or_else(|| Path::new("")).join(&path);
+            let full_path = full_path.to_string_lossy().into_owned();
+            let bytes = std::fs::read(&full_path).map_err(|err| {
+                let message = format!("Failed to include \"{}\"", full_path);
+                self.error_at(pos, CompileError::caused_by(message, err).message)
+            })?;
+            let source = decode_source(bytes, &full_path, self.options)?;
+            let mut parser = Parser::new(self.output, &source, &full_path, self.options);
+            parser.output.file_static_bases[parser.file_index] = self.static_base;
+            parser.static_base = self.static_base;
+            parser.include_depth = self.include_depth + 1;
+            parser.included_in = self.included_in.or(Some(self.file_index));
+            while parser.advance_command()? {}
+        }
+        Ok(())
+    }
+
+    /// Grabs the next symbol (contiguous group of characters without whitespace) and advances the
+    /// internal pointer beyond that point.
+    fn advance_symbol(&mut self) -> Option<(SavedPosition, &str)> {
+        self.skip_whitespace();
         let position = self.save_pos();
         let mut end_index = 0;
         loop {
             if end_index >= self.source.len() {
                 break;
             }
-            if let Some(next_char) = (&self.source[end_index..]).chars().next() {
-                if next_char.is_whitespace() {
+            if let Some(next_char) = self.source[end_index..].chars().next() {
+                // There is no need for whitespace before a comment that isn't `//`, since the
+                // comment character can't be part of a symbol.
+                if next_char.is_whitespace() || Some(next_char) == self.options.comment_char {
                     break;
                 } else {
                     end_index += next_char.len_utf8();
@@ -165,6 +333,12 @@ impl<'a> Parser<'a> {
     /// Parses the next command. Asserts that the current parser state is Command. Updates the
     /// parser state according to what command was read. Returns false if EOF has been reached.
     fn advance_command(&mut self) -> ParseResult<bool> {
+        self.skip_whitespace();
+        self.parse_includes()?;
+        if self.peek() == Some('!') {
+            self.advance_raw_asm()?;
+            return Ok(true);
+        }
         let next = if let Some(next) = self.advance_symbol() {
             next
         } else {
@@ -173,105 +347,237 @@ impl<'a> Parser<'a> {
         let (pos, symbol) = next;
         let command_name = CommandName::from_name(symbol);
         if let Some(command_name) = command_name {
-            self.advance_command_arguments(command_name)?;
+            self.command_pos = pos;
+            let result = self.advance_command_arguments(command_name);
+            result.map_err(|err| in_arguments_to(err, command_name))?;
             Ok(true)
         } else {
-            let expected = CommandName::all_names()
-                .iter()
-                .chain(ArithmeticOpcode::all_names().iter());
+            let expected = CommandName::expected_names();
             // Because lifetime problems.
             let symbol = symbol.to_owned();
-            Err(self.expected_one_of_found_error_message(pos, expected, &symbol[..]))
+            Err(self.expected_one_of_found_error_message(pos, expected.iter(), &symbol[..]))
+        }
+    }
+
+    /// Parses a line like `! @SP`, which is copied into the output as it is.
+    fn advance_raw_asm(&mut self) -> ParseResult {
+        let pos = self.save_pos();
+        self.advance();
+        let line_end = self.source.find('\n').unwrap_or(self.source.len());
+        let code = self.source[..line_end].trim().to_owned();
+        self.current_col += self.source[..line_end].chars().count();
+        self.source = &self.source[line_end..];
+        if !self.options.allow_unknown_command {
+            let message =
+                "Raw assembly lines starting with \"!\" are only allowed with --allow-unknown-command."
+                    .to_owned();
+            return Err(self.error_at(pos, message));
+        }
+        if code.is_empty() {
+            let message = "Expected assembly code after \"!\".".to_owned();
+            return Err(self.error_at(pos, message));
         }
+        self.command_pos = pos;
+        self.push_command(VmCommand::RawAsm(code));
+        Ok(())
     }
 
-    fn advance_mem_segment(&mut self) -> ParseResult<(SavedPosition, MemorySegment)> {
+    /// Returns `None` for the `shared` pseudo-segment. `command` is the name of the command the
+    /// segment is for, used to suggest a fix when the segment and index are swapped.
+    fn advance_mem_segment(
+        &mut self,
+        command: &str,
+    ) -> ParseResult<(SavedPosition, Option<MemorySegment>)> {
+        let names = MemorySegment::all_names()
+            .iter()
+            .chain(std::iter::once(&SHARED_SEGMENT));
         if let Some((pos, symbol)) = self.advance_symbol() {
+            if symbol == SHARED_SEGMENT {
+                return Ok((pos, None));
+            }
             let segment = MemorySegment::from_name(symbol);
             let symbol = symbol.to_owned();
-            let segment = segment.ok_or_else(|| {
-                self.expected_one_of_found_error_message(
-                    pos,
-                    MemorySegment::all_names().iter(),
-                    &symbol[..],
-                )
-            })?;
-            Ok((pos, segment))
+            // Like `push 3 local` instead of `push local 3`.
+            let next = self.source.split_whitespace().next();
+            let swapped = next.filter(|next| MemorySegment::from_name(next).is_some());
+            if let (None, Some(next), true) = (segment, swapped, symbol.parse::<usize>().is_ok()) {
+                let message = format!(
+                    "Expected a segment, got \"{0}\" instead. Did you mean \"{1} {2} {0}\"?",
+                    symbol, command, next
+                );
+                return Err(self.error_at(pos, message));
+            }
+            let segment = segment
+                .ok_or_else(|| self.expected_one_of_found_error_message(pos, names, &symbol[..]))?;
+            Ok((pos, Some(segment)))
         } else {
-            Err(self.expected_one_of_eof_error_message(MemorySegment::all_names().iter()))
+            Err(self.expected_one_of_eof_error_message(names))
         }
     }
 
     fn advance_constant(&mut self) -> ParseResult<usize> {
         if let Some((pos, symbol)) = self.advance_symbol() {
             let symbol = symbol.to_owned();
-            let parsed = symbol.parse::<usize>();
-            let parsed = parsed.map_err(|_err| {
-                let symbol = symbol.to_owned();
-                let footer = self.error_footer(pos);
-                format!(
-                    "Expected a nonnegative integer, got \"{}\" instead.{}",
-                    symbol, footer
-                )
+            // Hexadecimal and binary literals are also allowed, for writing bit patterns.
+            let (digits, radix, kind) = if let Some(digits) = symbol.strip_prefix("0x") {
+                (digits, 16, "a hexadecimal integer after \"0x\"")
+            } else if let Some(digits) = symbol.strip_prefix("0b") {
+                (digits, 2, "a binary integer after \"0b\"")
+            } else {
+                (&symbol[..], 10, "a nonnegative integer")
+            };
+            let valid = !digits.is_empty() && digits.chars().all(|c| c.is_digit(radix));
+            let parsed = usize::from_str_radix(digits, radix).ok().filter(|_| valid);
+            let parsed = parsed.ok_or_else(|| {
+                let message = format!("Expected {}, got \"{}\" instead.", kind, symbol);
+                self.error_at(pos, message)
             })?;
-            if parsed > 32767 {
-                let symbol = symbol.to_owned();
-                let footer = self.error_footer(pos);
-                Err(format!(
-                    "The integer \"{}\" is too big (expected 32767 or below).{}",
-                    symbol, footer
-                )
-                .into())
+            let max = self.options.max_constant();
+            if parsed > max {
+                let message = format!(
+                    "The integer \"{}\" is too big (expected {} or below).",
+                    symbol, max
+                );
+                Err(self.error_at(pos, message))
             } else {
                 Ok(parsed)
             }
         } else {
-            let footer = self.error_footer(self.save_pos());
-            Err(format!("Unexpected end of file, expected an integer.{}", footer).into())
+            let message = "Unexpected end of file, expected an integer.".to_owned();
+            Err(self.error_at(self.save_pos(), message))
+        }
+    }
+
+    /// Parses the number of arguments or locals of a call or function, making sure they can
+    /// actually fit on the stack.
+    fn advance_frame_size(&mut self, what: &str) -> ParseResult<usize> {
+        self.skip_whitespace();
+        let pos = self.save_pos();
+        let size = self.advance_constant()?;
+        if size > self.options.max_frame_size {
+            let message = format!(
+                "Using {} {} would overflow the stack (expected {} or below).",
+                size, what, self.options.max_frame_size
+            );
+            Err(self.error_at(pos, message))
+        } else {
+            Ok(size)
+        }
+    }
+
+    /// Parses an identifier written in quotes, which is allowed to contain any character. Assumes
+    /// the opening quote is the next character. The result is mangled into a legal identifier.
+    fn advance_quoted_identifier(&mut self) -> ParseResult<String> {
+        let pos = self.save_pos();
+        self.advance();
+        let mut name = String::new();
+        loop {
+            match self.peek() {
+                Some('"') => break,
+                Some('\\') => {
+                    self.advance();
+                    match self.peek() {
+                        Some(escaped) if escaped == '"' || escaped == '\\' => name.push(escaped),
+                        _ => {
+                            let message = "Expected either '\\' or '\"' after '\\' in a quoted \
+                                identifier."
+                                .to_owned();
+                            return Err(self.error_at(self.save_pos(), message));
+                        }
+                    }
+                }
+                Some('\n') | None => {
+                    let message = "Quoted identifier is missing its closing quote.".to_owned();
+                    return Err(self.error_at(pos, message));
+                }
+                Some(other) => name.push(other),
+            }
+            self.advance();
+        }
+        // Skip the closing quote.
+        self.advance();
+        if name.is_empty() {
+            let message = "Quoted identifiers cannot be empty.".to_owned();
+            return Err(self.error_at(pos, message));
+        }
+        self.check_ascii(pos, &name)?;
+        Ok(mangle_identifier(&name))
+    }
+
+    /// With `Options::ascii_only`, makes sure every character of an identifier is ASCII.
+    fn check_ascii(&self, pos: SavedPosition, name: &str) -> ParseResult {
+        if !self.options.ascii_only {
+            return Ok(());
+        }
+        match name.chars().find(|ch| !ch.is_ascii()) {
+            Some(ch) => {
+                let message = format!(
+                    "Encountered non-ASCII character '{}' in identifier \"{}\", which is not \
+                    allowed with --ascii-only, suggested ASCII name: \"{}\".",
+                    ch,
+                    name,
+                    sanitize_identifier(name)
+                );
+                Err(self.error_at(pos, message))
+            }
+            None => Ok(()),
         }
     }
 
     fn advance_identifier(&mut self) -> ParseResult<String> {
+        self.skip_whitespace();
+        if self.peek() == Some('"') {
+            return self.advance_quoted_identifier();
+        }
         if let Some((pos, symbol)) = self.advance_symbol() {
             let symbol = symbol.to_owned();
+            self.check_ascii(pos, &symbol)?;
             for (idx, ch) in symbol.chars().enumerate() {
                 // If it is an illegal character or it is the first character and is a number...
-                if !(ch.is_ascii_alphanumeric() || ch == '_' || ch == '.' || ch == ':')
-                    || (ch.is_ascii_digit() && idx == 0)
-                {
-                    let footer = self.error_footer(pos);
-                    return Err(format!(
-                        "Encountered illegal character \'{}\' in identifier \"{}\".{}",
-                        ch, symbol, footer
-                    )
-                    .into());
+                if !is_identifier_char(ch) || (ch.is_ascii_digit() && idx == 0) {
+                    let message = format!(
+                        "Encountered illegal character \'{}\' in identifier \"{}\", suggested \
+                        legal name: \"{}\".",
+                        ch,
+                        symbol,
+                        sanitize_identifier(&symbol)
+                    );
+                    return Err(self.error_at(pos, message));
                 }
             }
             Ok(symbol)
         } else {
-            let footer = self.error_footer(self.save_pos());
-            Err(format!("Unexpected end of file, expected an identifier.{}", footer).into())
+            let message = "Unexpected end of file, expected an identifier.".to_owned();
+            Err(self.error_at(self.save_pos(), message))
         }
     }
 
     fn parse_push_pop_args(&mut self, is_push: bool) -> ParseResult {
-        let (msp, memory_segment) = self.advance_mem_segment()?;
-        let mut index = self.advance_constant()?;
-        if let MemorySegment::Static = memory_segment {
-            index += self.static_base;
-            // Ensures that any files parsed later will not use this same spot to store a static
-            // variable.
-            self.output.increase_static_size(index + 1);
-        }
-        self.output.push_command(if is_push {
+        let (msp, memory_segment, index) =
+            match self.advance_mem_segment(if is_push { "push" } else { "pop" })? {
+                (msp, Some(memory_segment)) => {
+                    let mut index = self.advance_constant()?;
+                    if let MemorySegment::Static = memory_segment {
+                        index += self.static_base;
+                        // Ensures that any files parsed later will not use this same spot to store a
+                        // static variable.
+                        self.output.increase_static_size(index + 1);
+                    }
+                    (msp, memory_segment, index)
+                }
+                (msp, None) => {
+                    let name = self.advance_identifier()?;
+                    let index = self.advance_constant()?;
+                    let slot = self.output.shared_static_slot(&name, index);
+                    (msp, MemorySegment::Static, slot)
+                }
+            };
+        self.push_command(if is_push {
             VmCommand::Push(memory_segment, index)
         } else {
             if memory_segment == MemorySegment::Constant {
-                let footer = self.error_footer(msp);
-                Err(format!(
-                    "It is illegal to pop data into the `const` segment.{}",
-                    footer
-                ))?;
+                let message = "It is illegal to pop data into the `const` segment.".to_owned();
+                Err(self.error_at(msp, message))?;
             }
             VmCommand::Pop(memory_segment, index)
         });
@@ -281,42 +587,693 @@ impl<'a> Parser<'a> {
     /// Takes us out of the Argument state assuming we have found all needed arguments.
     fn advance_command_arguments(&mut self, command: CommandName) -> ParseResult {
         match command {
-            CommandName::Arithmetic(op) => self.output.push_command(VmCommand::Arithmetic(op)),
+            CommandName::Arithmetic(op) => self.push_command(VmCommand::Arithmetic(op)),
             CommandName::Call => {
                 let fn_name = self.advance_identifier()?;
-                let num_args = self.advance_constant()?;
+                let num_args = self.advance_frame_size("arguments")?;
                 let command = VmCommand::Call { fn_name, num_args };
-                self.output.push_command(command);
+                self.push_command(command);
             }
             CommandName::Function => {
                 let ident = self.advance_identifier()?;
-                let num_locals = self.advance_constant()?;
-                self.output.push_command(VmCommand::Label(ident));
-                self.output.push_command(VmCommand::FnSetup { num_locals });
+                // Forgetting the count would otherwise give an error that doesn't mention it.
+                self.skip_whitespace();
+                if !self.source.starts_with(|c: char| c.is_ascii_digit()) {
+                    let pos = self.save_pos();
+                    let message = match self.advance_symbol() {
+                        Some((_, symbol)) => format!(
+                            "Expected the number of locals for the function \"{}\", got \"{}\" instead.",
+                            ident, symbol
+                        ),
+                        None => format!(
+                            "Unexpected end of file, expected the number of locals for the function \"{}\".",
+                            ident
+                        ),
+                    };
+                    return Err(self.error_at(pos, message));
+                }
+                let num_locals = self.advance_frame_size("locals")?;
+                self.push_command(VmCommand::Label(ident));
+                self.push_command(VmCommand::FnSetup { num_locals });
             }
             CommandName::Goto => {
                 let ident = self.advance_identifier()?;
-                self.output.push_command(VmCommand::Goto(ident))
+                self.push_command(VmCommand::Goto(ident))
             }
             CommandName::IfGoto => {
                 let ident = self.advance_identifier()?;
-                self.output.push_command(VmCommand::IfGoto(ident))
+                self.push_command(VmCommand::IfGoto(ident))
             }
             CommandName::Label => {
                 let ident = self.advance_identifier()?;
-                self.output.push_command(VmCommand::Label(ident))
+                self.push_command(VmCommand::Label(ident))
             }
             CommandName::Push => self.parse_push_pop_args(true)?,
             CommandName::Pop => self.parse_push_pop_args(false)?,
-            CommandName::Return => self.output.push_command(VmCommand::Return),
+            CommandName::Return => self.push_command(VmCommand::Return),
+            CommandName::Nop => self.push_command(VmCommand::Nop),
         }
         Ok(())
     }
 }
 
-pub fn parse(into: &mut VmProgram, source: &str, file_path: &str) -> Result<(), Box<dyn Error>> {
-    let mut parser = Parser::new(into, source, file_path);
+/// Turns the contents of a source file into a string, with an error pointing at the first invalid
+/// byte if it isn't UTF-8.
+pub fn decode_source(
+    bytes: Vec<u8>,
+    file_path: &str,
+    options: &Options,
+) -> Result<String, Box<dyn Error>> {
+    String::from_utf8(bytes).map_err(|err| {
+        let offset = err.utf8_error().valid_up_to();
+        let before = String::from_utf8_lossy(&err.as_bytes()[..offset]);
+        let line_start = before.rfind('\n').map(|index| index + 1).unwrap_or(0);
+        let location = Location {
+            file: file_path.to_owned(),
+            line: before.matches('\n').count() + 1,
+            col: before[line_start..].chars().count() + options.column_base,
+        };
+        let message = format!(
+            "The file is not valid UTF-8 text, the first invalid byte is at offset {} (0x{:02x}).",
+            offset,
+            err.as_bytes()[offset]
+        );
+        CompileError::at(message, location).into()
+    })
+}
+
+pub fn parse(
+    into: &mut VmProgram,
+    source: &str,
+    file_path: &str,
+    options: &Options,
+) -> Result<(), Box<dyn Error>> {
+    let mut parser = Parser::new(into, source, file_path, options);
     // Parse commands until we encounter an error or there are no commands left to parse.
     while parser.advance_command()? {}
     Ok(())
 }
+
+/// Like `parse`, but hands each command to `visitor` as soon as it is parsed instead of adding it
+/// to `into`, so a big file never has to be in memory as commands all at once. Everything else
+/// still goes into `into` the same as with `parse`, like the file and the static variables it
+/// uses, so later files number their static variables the same way too.
+pub fn parse_with(
+    into: &mut VmProgram,
+    source: &str,
+    file_path: &str,
+    options: &Options,
+    visitor: &mut dyn FnMut(VmCommand),
+) -> Result<(), Box<dyn Error>> {
+    let start = into.commands.len();
+    let mut parser = Parser::new(into, source, file_path, options);
+    while parser.advance_command()? {
+        parser.output.info.truncate(start);
+        parser
+            .output
+            .commands
+            .drain(start..)
+            .for_each(&mut *visitor);
+    }
+    Ok(())
+}
+
+/// Parses a file that arrives in pieces, like over a network connection. Only whole lines are
+/// parsed as they come in, and a command that isn't finished by the end of what has been fed so
+/// far is held back until the rest of it arrives. The result is the same as parsing the whole file
+/// at once.
+pub struct IncrementalParser<'a> {
+    output: &'a mut VmProgram,
+    options: &'a Options,
+    file_index: usize,
+    /// Source that has been fed in but not parsed yet.
+    buffer: String,
+    /// Where the start of `buffer` is in the file.
+    current_line: usize,
+    current_col: usize,
+    /// Annotations from comments in source that has already been parsed.
+    source_annotation: Option<String>,
+    local_names: Option<Vec<String>>,
+}
+
+impl<'a> IncrementalParser<'a> {
+    pub fn new(output: &'a mut VmProgram, file_path: &str, options: &'a Options) -> Self {
+        let file_index = output.add_file(file_path);
+        Self {
+            output,
+            options,
+            file_index,
+            buffer: String::new(),
+            current_line: 1,
+            current_col: 1,
+            source_annotation: None,
+            local_names: None,
+        }
+    }
+
+    /// Adds more of the file, parsing every command that is complete so far.
+    pub fn feed(&mut self, more: &str) -> Result<(), Box<dyn Error>> {
+        self.buffer.push_str(more);
+        let complete_lines = self.buffer.rfind('\n').map(|index| index + 1).unwrap_or(0);
+        self.parse_buffered(complete_lines, false)
+    }
+
+    /// Parses whatever is left, once the whole file has been fed in.
+    pub fn finish(mut self) -> Result<(), Box<dyn Error>> {
+        let len = self.buffer.len();
+        self.parse_buffered(len, true)
+    }
+
+    /// Parses the first `len` bytes of the buffer. Unless this is the end of the file, an error
+    /// caused by running out of source just means the command continues in the next piece, so
+    /// that command is undone and kept in the buffer.
+    fn parse_buffered(&mut self, len: usize, end_of_file: bool) -> Result<(), Box<dyn Error>> {
+        let mut parser = Parser::continuing(
+            self.output,
+            &self.buffer[..len],
+            self.file_index,
+            self.options,
+        );
+        parser.current_line = self.current_line;
+        parser.current_col = self.current_col;
+        parser.source_annotation = self.source_annotation.take();
+        parser.local_names = self.local_names.take();
+        loop {
+            let before = (
+                parser.source,
+                parser.save_pos(),
+                parser.source_annotation.clone(),
+                parser.local_names.clone(),
+            );
+            let output_len = parser.output.commands.len();
+            let static_size = parser.output.static_size;
+            let num_shared_statics = parser.output.shared_statics.len();
+            let num_files = parser.output.files.len();
+            match parser.advance_command() {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(_) if !end_of_file && parser.source.trim().is_empty() => {
+                    let (source, pos, source_annotation, local_names) = before;
+                    parser.source = source;
+                    parser.current_line = pos.0;
+                    parser.current_col = pos.1;
+                    parser.source_annotation = source_annotation;
+                    parser.local_names = local_names;
+                    parser.output.commands.truncate(output_len);
+                    parser.output.info.truncate(output_len);
+                    parser.output.static_size = static_size;
+                    parser.output.shared_statics.truncate(num_shared_statics);
+                    parser.output.files.truncate(num_files);
+                    parser.output.file_static_bases.truncate(num_files);
+                    break;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        let parsed = len - parser.source.len();
+        self.current_line = parser.current_line;
+        self.current_col = parser.current_col;
+        self.source_annotation = parser.source_annotation.take();
+        self.local_names = parser.local_names.take();
+        self.buffer.drain(..parsed);
+        Ok(())
+    }
+}
+
+/// Splits source code into symbols the same way the parser does, skipping whitespace and
+/// comments, without trying to make commands out of them. Each symbol comes with the line and
+/// column it starts at.
+pub fn tokens(source: &str, options: &Options) -> Vec<(usize, usize, String)> {
+    let mut scratch = VmProgram::new();
+    let mut parser = Parser::new(&mut scratch, source, "", options);
+    let mut tokens = Vec::new();
+    while let Some((pos, symbol)) = parser.advance_symbol() {
+        let col = pos.1 - 1 + options.column_base;
+        tokens.push((pos.0, col, symbol.to_owned()));
+    }
+    tokens
+}
+
+/// The file name errors from `parse_line` are reported in.
+pub const LINE_FILE_PATH: &str = "<line>";
+
+/// Parses a single command, for feeding a program in one line at a time. Every line is treated as
+/// part of the same file, so static variables are shared between them. Returns false if the line
+/// was empty or only had a comment.
+pub fn parse_line(into: &mut VmProgram, line: &str) -> Result<bool, Box<dyn Error>> {
+    let file_index = match into.files.iter().rposition(|file| file == LINE_FILE_PATH) {
+        Some(index) => index,
+        None => into.add_file(LINE_FILE_PATH),
+    };
+    let options = Options::default();
+    let num_commands = into.commands.len();
+    let static_size = into.static_size;
+    let num_shared_statics = into.shared_statics.len();
+    let num_files = into.files.len();
+    let mut parser = Parser::continuing(into, line, file_index, &options);
+    let result = match parser.advance_command() {
+        Ok(true) => match parser.advance_symbol() {
+            Some((pos, symbol)) => {
+                let message = format!(
+                    "Expected only one command, but found \"{}\" after it.",
+                    symbol
+                );
+                Err(parser.error_at(pos, message))
+            }
+            None => Ok(true),
+        },
+        other => other,
+    };
+    // A line with a problem shouldn't leave any of itself behind in the program.
+    if result.is_err() {
+        into.commands.truncate(num_commands);
+        into.info.truncate(num_commands);
+        into.static_size = static_size;
+        into.shared_statics.truncate(num_shared_statics);
+        into.files.truncate(num_files);
+        into.file_static_bases.truncate(num_files);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(source: &str, options: &Options) -> CompileError {
+        let mut program = VmProgram::new();
+        let err = parse(&mut program, source, "Test.vm", options).unwrap_err();
+        *err.downcast::<CompileError>().unwrap()
+    }
+
+    #[test]
+    fn columns_start_at_the_column_base() {
+        let source = "push constant 1\n  push locl 0\n";
+        let one_based = parse_error(source, &Options::default());
+        let zero_based = parse_error(
+            source,
+            &Options {
+                column_base: 0,
+                ..Options::default()
+            },
+        );
+        assert_eq!(one_based.location.unwrap().col, 8);
+        assert_eq!(zero_based.location.unwrap().col, 7);
+    }
+
+    #[test]
+    fn constants_can_be_hexadecimal_or_binary() {
+        let mut program = VmProgram::new();
+        let source = "push constant 0x7FFF\npush constant 0b101\n";
+        parse(&mut program, source, "Test.vm", &Options::default()).unwrap();
+        assert_eq!(
+            program.commands,
+            vec![
+                VmCommand::Push(MemorySegment::Constant, 32767),
+                VmCommand::Push(MemorySegment::Constant, 5),
+            ]
+        );
+        let err = parse_error("push constant 0x8000\n", &Options::default());
+        assert!(err
+            .message
+            .contains("\"0x8000\" is too big (expected 32767 or below)"));
+        let err = parse_error("push constant 0xG\n", &Options::default());
+        assert!(err
+            .message
+            .contains("expected a hexadecimal integer after \"0x\""));
+    }
+
+    #[test]
+    fn frames_have_to_fit_on_the_stack() {
+        let err = parse_error("function Foo 2000\n", &Options::default());
+        assert!(err
+            .message
+            .contains("using 2000 locals would overflow the stack (expected 1792 or below)."));
+        assert_eq!(err.location.unwrap().col, 14);
+        let err = parse_error("call Foo 1793\n", &Options::default());
+        assert!(err
+            .message
+            .contains("using 1793 arguments would overflow the stack"));
+        let mut program = VmProgram::new();
+        let source = "function Foo 1792\ncall Foo 3\n";
+        parse(&mut program, source, "Test.vm", &Options::default()).unwrap();
+        assert_eq!(program.commands.len(), 3);
+    }
+
+    #[test]
+    fn lines_build_up_a_program() {
+        let mut program = VmProgram::new();
+        assert!(parse_line(&mut program, "push constant 1").unwrap());
+        assert!(!parse_line(&mut program, "// just a comment").unwrap());
+        assert!(parse_line(&mut program, "push static 0").unwrap());
+        let err = parse_line(&mut program, "pop static 1 extra").unwrap_err();
+        assert!(err.to_string().contains("found \"extra\" after it"));
+        assert!(parse_line(&mut program, "pop shared Counter 0 extra").is_err());
+        assert!(parse_line(&mut program, "add").unwrap());
+        assert_eq!(
+            program.commands,
+            vec![
+                VmCommand::Push(MemorySegment::Constant, 1),
+                VmCommand::Push(MemorySegment::Static, 0),
+                VmCommand::Arithmetic(crate::vm_program::ArithmeticOpcode::Add),
+            ]
+        );
+        assert_eq!(program.info.len(), 3);
+        assert_eq!(program.static_size, 1);
+        assert!(program.shared_statics.is_empty());
+        assert_eq!(program.files, vec![LINE_FILE_PATH.to_owned()]);
+    }
+
+    #[test]
+    fn tokens_skip_comments_and_know_their_positions() {
+        let source = "// header\npush  constant 1 // one\n\n  add\n";
+        let tokens = tokens(source, &Options::default());
+        let expected = [
+            (2, 1, "push"),
+            (2, 7, "constant"),
+            (2, 16, "1"),
+            (4, 3, "add"),
+        ];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|(line, col, token)| (*line, *col, token.to_string()))
+            .collect();
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn files_share_shared_statics() {
+        let options = Options::default();
+        let mut program = VmProgram::new();
+        let first = "push static 0\npush shared Counter 0\n";
+        parse(&mut program, first, "A.vm", &options).unwrap();
+        let second = "pop shared Counter 0\npop static 0\npush shared Counter 1\n";
+        parse(&mut program, second, "B.vm", &options).unwrap();
+        let slots: Vec<_> = program
+            .commands
+            .iter()
+            .map(|command| match command {
+                VmCommand::Push(MemorySegment::Static, slot)
+                | VmCommand::Pop(MemorySegment::Static, slot) => *slot,
+                other => panic!("Unexpected command {}", other),
+            })
+            .collect();
+        // Shared statics are given slots from the end of the segment.
+        assert_eq!(slots, vec![0, 239, 239, 1, 238]);
+        assert_eq!(program.static_slots_used(), 4);
+    }
+
+    #[test]
+    fn invalid_utf8_points_at_the_bad_byte() {
+        let bytes = b"push constant 1\npush \xffconstant 2\n".to_vec();
+        let err = decode_source(bytes, "Test.vm", &Options::default()).unwrap_err();
+        let err = err.downcast::<CompileError>().unwrap();
+        assert_eq!(
+            err.message,
+            "The file is not valid UTF-8 text, the first invalid byte is at offset 21 (0xff)."
+        );
+        let location = err.location.unwrap();
+        assert_eq!((location.line, location.col), (2, 6));
+    }
+
+    #[test]
+    fn incremental_parsing_matches_parsing_at_once() {
+        let options = Options::default();
+        let source = "// @src Main.jack:1\nfunction Main.main 1\npush static 3\npop local 0\n\
+            push constant 12\nreturn\n";
+        let mut whole = VmProgram::new();
+        parse(&mut whole, source, "Test.vm", &options).unwrap();
+        for split in 1..source.len() {
+            let mut pieces = VmProgram::new();
+            let mut parser = IncrementalParser::new(&mut pieces, "Test.vm", &options);
+            parser.feed(&source[..split]).unwrap();
+            parser.feed(&source[split..]).unwrap();
+            parser.finish().unwrap();
+            assert_eq!(pieces.commands, whole.commands, "split at {}", split);
+            assert_eq!(pieces.info, whole.info, "split at {}", split);
+            assert_eq!(pieces.static_size, whole.static_size);
+        }
+    }
+
+    #[test]
+    fn missing_local_count_names_the_function() {
+        let options = Options::default();
+        let err = parse_error("function Foo", &options);
+        assert_eq!(
+            err.message,
+            "While parsing the arguments to \"function\", unexpected end of file, expected the \
+            number of locals for the function \"Foo\"."
+        );
+        let err = parse_error("function Foo\npush constant 1\n", &options);
+        assert_eq!(
+            err.message,
+            "While parsing the arguments to \"function\", expected the number of locals for the \
+            function \"Foo\", got \"push\" instead."
+        );
+        assert_eq!(err.location.unwrap().line, 2);
+    }
+
+    #[test]
+    fn included_files_share_statics_and_nest_up_to_a_limit() {
+        let dir =
+            std::env::temp_dir().join(format!("translator-test-{}-include", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Lib.vm"), "push static 1\n").unwrap();
+        std::fs::write(
+            dir.join("Loop.vm"),
+            "push constant 1\n// @include Loop.vm\n",
+        )
+        .unwrap();
+        let main_path = dir.join("Main.vm").to_string_lossy().into_owned();
+        let options = Options::default();
+        let mut program = VmProgram::new();
+        let source = "push static 0\n// @include Lib.vm\npop static 0\n";
+        parse(&mut program, source, &main_path, &options).unwrap();
+        assert_eq!(
+            program.commands,
+            vec![
+                VmCommand::Push(MemorySegment::Static, 0),
+                VmCommand::Push(MemorySegment::Static, 1),
+                VmCommand::Pop(MemorySegment::Static, 0),
+            ]
+        );
+        assert_eq!(program.files.len(), 2);
+        assert_eq!(
+            program.location(1).unwrap().file,
+            dir.join("Lib.vm").to_string_lossy()
+        );
+
+        let options = Options {
+            max_include_depth: 3,
+            ..Options::default()
+        };
+        let loop_path = dir.join("Loop.vm").to_string_lossy().into_owned();
+        let mut program = VmProgram::new();
+        let err = parse(&mut program, "// @include Loop.vm\n", &loop_path, &options).unwrap_err();
+        let err = err.downcast::<CompileError>().unwrap();
+        assert_eq!(
+            err.message,
+            "Files are included more than 3 deep (set by --max-include-depth), which usually \
+            means a file includes itself."
+        );
+        assert_eq!(
+            err.location
+                .map(|location| (location.file, location.line, location.col)),
+            Some((loop_path, 2, 4))
+        );
+        let err = parse_error("// @include Missing.vm\n", &options);
+        assert!(err
+            .message
+            .starts_with("Failed to include \"Missing.vm\", caused by:\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn suggests_swapping_the_segment_and_index() {
+        let options = Options::default();
+        let err = parse_error("push 3 local\n", &options);
+        assert_eq!(
+            err.message,
+            "While parsing the arguments to \"push\", expected a segment, got \"3\" instead. Did you \
+            mean \"push local 3\"?"
+        );
+        assert_eq!(err.location.unwrap().col, 6);
+        let err = parse_error("pop 3 frog\n", &options);
+        assert!(
+            err.message.contains("found unknown symbol \"3\""),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn suggests_a_legal_name() {
+        let options = Options::default();
+        let err = parse_error("function Foo-Bar 0\n", &options);
+        assert_eq!(
+            err.message,
+            "While parsing the arguments to \"function\", encountered illegal character '-' in \
+            identifier \"Foo-Bar\", suggested legal name: \"Foo_Bar\"."
+        );
+        let err = parse_error("label 1st\n", &options);
+        assert!(
+            err.message.ends_with("suggested legal name: \"_1st\"."),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn semicolon_comments_need_the_option() {
+        let source = "; a whole line\npush constant 1;right after\npop temp 0 ; with a space\n";
+        let options = Options {
+            comment_char: Some(';'),
+            ..Options::default()
+        };
+        let mut program = VmProgram::new();
+        parse(&mut program, source, "Test.vm", &options).unwrap();
+        assert_eq!(
+            program.commands,
+            vec![
+                VmCommand::Push(MemorySegment::Constant, 1),
+                VmCommand::Pop(MemorySegment::Temp, 0)
+            ]
+        );
+        // `//` still works too.
+        parse(&mut program, "// comment\nadd\n", "Other.vm", &options).unwrap();
+        let err = parse_error(source, &Options::default());
+        assert_eq!(err.location.unwrap().line, 1);
+    }
+
+    #[test]
+    fn argument_errors_name_their_command() {
+        let options = Options::default();
+        for (source, command) in &[
+            ("push frog 0\n", "push"),
+            ("push local x\n", "push"),
+            ("call Main.f x\n", "call"),
+            ("call 3 0\n", "call"),
+            ("function Main.f\n", "function"),
+            ("function Main.f two\n", "function"),
+        ] {
+            let err = parse_error(source, &options);
+            let prefix = format!("While parsing the arguments to \"{}\", ", command);
+            assert!(err.message.starts_with(&prefix), "{}", err.message);
+        }
+    }
+
+    #[test]
+    fn random_bytes_never_panic() {
+        let pieces: &[&[u8]] = &[
+            b"push ",
+            b"pop ",
+            b"function ",
+            b"call ",
+            b"return",
+            b"label ",
+            b"if-goto ",
+            b"constant ",
+            b"local ",
+            b"static ",
+            b"pointer ",
+            b"add",
+            b"Main.f",
+            b"0",
+            b"32767",
+            b"99999999999999999999",
+            b"\n",
+            b"\r\n",
+            b" ",
+            b"\t",
+            b"//",
+            b"// @",
+            b"/",
+            b"-",
+            b"\"",
+            b"\xff",
+            b"\xc3",
+            b"\xe2\x82",
+            b"\xf0\x9f\x98\x80",
+            b"\0",
+        ];
+        // A fixed xorshift generator, so that any failure can be reproduced.
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let options = Options::default();
+        for _ in 0..5000 {
+            let mut bytes = Vec::new();
+            for _ in 0..next() % 24 {
+                let choice = next();
+                if choice % 4 == 0 {
+                    bytes.push((choice >> 8) as u8);
+                } else {
+                    bytes.extend_from_slice(pieces[(choice >> 8) as usize % pieces.len()]);
+                }
+            }
+            if let Ok(source) = decode_source(bytes, "Test.vm", &options) {
+                let _ = parse(&mut VmProgram::new(), &source, "Test.vm", &options);
+            }
+        }
+    }
+
+    #[test]
+    fn ascii_only_rejects_other_characters() {
+        let options = Options {
+            ascii_only: true,
+            ..Options::default()
+        };
+        let err = parse_error("function Café.run 0\n", &options);
+        assert_eq!(
+            err.message,
+            "While parsing the arguments to \"function\", encountered non-ASCII character 'é' \
+            in identifier \"Café.run\", which is not allowed with --ascii-only, suggested ASCII \
+            name: \"Caf_.run\"."
+        );
+        assert_eq!(err.location.unwrap().col, 10);
+        // Quoted names can have them without the option.
+        let source = "call \"Café.run\" 0\n";
+        let mut program = VmProgram::new();
+        parse(&mut program, source, "Test.vm", &Options::default()).unwrap();
+        let err = parse_error(source, &options);
+        assert!(
+            err.message.contains("non-ASCII character 'é'"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn visitor_sees_what_a_batch_parse_makes() {
+        let options = Options::default();
+        let files = [
+            (
+                "Main.vm",
+                "function Main.main 0\npush static 1\ncall Other.f 1\nreturn\n",
+            ),
+            (
+                "Other.vm",
+                "function Other.f 0\npush static 0\npop static 2\nreturn\n",
+            ),
+        ];
+        let mut batch = VmProgram::new();
+        let mut streamed = VmProgram::new();
+        let mut visited = Vec::new();
+        for (path, source) in &files {
+            parse(&mut batch, source, path, &options).unwrap();
+            parse_with(&mut streamed, source, path, &options, &mut |command| {
+                visited.push(command)
+            })
+            .unwrap();
+        }
+        assert_eq!(visited, batch.commands);
+        assert!(streamed.commands.is_empty());
+        assert_eq!(streamed.static_size, batch.static_size);
+        assert_eq!(streamed.files, batch.files);
+        assert!(visited.contains(&VmCommand::Pop(MemorySegment::Static, 4)));
+    }
+}
@@ -0,0 +1,37 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    time::{Duration, Instant},
+};
+
+/// How long each phase of compilation took, in the order the phases first happened.
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    pub phases: Vec<(&'static str, Duration)>,
+}
+
+impl Profile {
+    /// Adds the time since `start` to a phase. Phases can be recorded more than once, like when
+    /// parsing multiple files, and the times are added together.
+    pub fn record(&mut self, phase: &'static str, start: Instant) {
+        let elapsed = start.elapsed();
+        match self.phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += elapsed,
+            None => self.phases.push((phase, elapsed)),
+        }
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Time taken:")?;
+        for (phase, duration) in &self.phases {
+            write!(
+                f,
+                "\n    {}: {:.3}ms",
+                phase,
+                duration.as_secs_f64() * 1000.0
+            )?;
+        }
+        Ok(())
+    }
+}
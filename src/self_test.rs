@@ -0,0 +1,103 @@
+//! A few small programs built into the translator along with the assembly they are known to
+//! translate into, so that a build can check itself with `--self-test`.
+
+use crate::{options::Options, parse, translate, vm_program::VmProgram};
+
+/// A program and the assembly it should translate into with the default options.
+struct Fixture {
+    name: &'static str,
+    source: &'static str,
+    expected: &'static str,
+}
+
+macro_rules! fixture {
+    ($name:literal) => {
+        Fixture {
+            name: $name,
+            source: include_str!(concat!("../fixtures/self_test/", $name, ".vm")),
+            expected: include_str!(concat!("../fixtures/self_test/", $name, ".asm")),
+        }
+    };
+}
+
+const FIXTURES: &[Fixture] = &[
+    fixture!("arithmetic"),
+    fixture!("memory"),
+    fixture!("functions"),
+];
+
+/// Translates one fixture, describing how the result differs from what was expected if it does.
+fn check(fixture: &Fixture) -> Result<(), String> {
+    let options = Options::default();
+    let mut program = VmProgram::default();
+    let file_path = format!("{}.vm", fixture.name);
+    parse::parse(&mut program, fixture.source, &file_path, &options)
+        .map_err(|err| format!("failed to parse: {}", err))?;
+    let result = translate::translate(program, &options)
+        .map_err(|err| format!("failed to translate: {}", err))?;
+    let mut expected_lines = fixture.expected.lines();
+    for (index, line) in result.lines().enumerate() {
+        match expected_lines.next() {
+            Some(expected) if expected == line => (),
+            Some(expected) => Err(format!(
+                "line {} is \"{}\" instead of \"{}\"",
+                index + 1,
+                line,
+                expected
+            ))?,
+            None => Err(format!(
+                "line {} is \"{}\" past the expected end",
+                index + 1,
+                line
+            ))?,
+        }
+    }
+    match expected_lines.next() {
+        Some(expected) => Err(format!("the output ends before \"{}\"", expected)),
+        None => Ok(()),
+    }
+}
+
+/// Checks every fixture, returning the name of each one along with what went wrong, if anything.
+pub fn run() -> Vec<(&'static str, Result<(), String>)> {
+    FIXTURES
+        .iter()
+        .map(|fixture| (fixture.name, check(fixture)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn differences_are_described() {
+        let correct = &FIXTURES[0];
+        let lines: Vec<_> = correct.expected.lines().collect();
+        let changed_line = lines
+            .iter()
+            .position(|line| line.starts_with("@SP"))
+            .unwrap();
+        let changed = correct.expected.replacen("@SP", "@LCL", 1);
+        let fixture = Fixture {
+            expected: Box::leak(changed.into_boxed_str()),
+            ..*correct
+        };
+        assert_eq!(
+            check(&fixture),
+            Err(format!(
+                "line {} is \"{}\" instead of \"{}\"",
+                changed_line + 1,
+                lines[changed_line],
+                lines[changed_line].replacen("@SP", "@LCL", 1)
+            ))
+        );
+        let fixture = Fixture {
+            expected: "",
+            ..*correct
+        };
+        assert!(check(&fixture)
+            .unwrap_err()
+            .ends_with("past the expected end"));
+    }
+}
@@ -0,0 +1,237 @@
+//! Working out how much of the stack each function needs, by following how every command changes
+//! the number of values on it.
+
+use crate::vm_program::{ArithmeticOpcode, Function, VmCommand, VmProgram};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+};
+
+/// A run of commands that always execute one after the other, from `start` up to but not
+/// including `end`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// How the number of values on the stack changes while a command runs. The first number is the
+/// most it goes up by at any point, and the second is how much it has changed by afterwards.
+fn stack_effect(command: &VmCommand) -> (isize, isize) {
+    use ArithmeticOpcode::*;
+    match command {
+        VmCommand::Arithmetic(Neg) | VmCommand::Arithmetic(Not) => (0, 0),
+        VmCommand::Arithmetic(_) => (0, -1),
+        VmCommand::Push(..) => (1, 1),
+        VmCommand::Pop(..) | VmCommand::IfGoto(_) => (0, -1),
+        // The return address and the caller's LCL, ARG, THIS, and THAT are saved on the stack
+        // above the arguments, which are replaced by the return value afterwards.
+        VmCommand::Call { num_args, .. } => (5, 1 - *num_args as isize),
+        VmCommand::FnSetup { num_locals } => (*num_locals as isize, *num_locals as isize),
+        _ => (0, 0),
+    }
+}
+
+/// Splits commands up into basic blocks. A new block starts at every label and after every
+/// command that might jump somewhere else.
+pub fn basic_blocks(commands: &[VmCommand]) -> Vec<BasicBlock> {
+    let mut blocks = Vec::new();
+    let mut start = 0;
+    for (index, command) in commands.iter().enumerate() {
+        if let (VmCommand::Label(_), true) = (command, index > start) {
+            blocks.push(BasicBlock { start, end: index });
+            start = index;
+        }
+        if command.is_block_leader_after() {
+            blocks.push(BasicBlock {
+                start,
+                end: index + 1,
+            });
+            start = index + 1;
+        }
+    }
+    if start < commands.len() {
+        blocks.push(BasicBlock {
+            start,
+            end: commands.len(),
+        });
+    }
+    blocks
+}
+
+/// The blocks execution can go to after each block. Jumps to labels outside of the commands are
+/// left out.
+fn successors(commands: &[VmCommand], blocks: &[BasicBlock]) -> Vec<Vec<usize>> {
+    let labels: HashMap<&str, usize> = blocks
+        .iter()
+        .enumerate()
+        .filter_map(|(index, block)| match &commands[block.start] {
+            VmCommand::Label(label) => Some((&label[..], index)),
+            _ => None,
+        })
+        .collect();
+    let mut successors = Vec::with_capacity(blocks.len());
+    for (index, block) in blocks.iter().enumerate() {
+        let last = &commands[block.end - 1];
+        let mut next = Vec::new();
+        if let VmCommand::Goto(label) | VmCommand::IfGoto(label) = last {
+            next.extend(labels.get(&label[..]));
+        }
+        if !last.is_terminator() && index + 1 < blocks.len() {
+            next.push(index + 1);
+        }
+        successors.push(next);
+    }
+    successors
+}
+
+/// How many values are on the stack when each block starts, given how many there are at the
+/// start of the first one. Blocks that can't be reached have None. Returns None if going around
+/// a loop leaves more values on the stack each time, since then there is no limit.
+pub fn block_entry_depths(
+    commands: &[VmCommand],
+    blocks: &[BasicBlock],
+    initial_depth: isize,
+) -> Option<Vec<Option<isize>>> {
+    let successors = successors(commands, blocks);
+    let mut entry_depths = vec![None; blocks.len()];
+    if blocks.is_empty() {
+        return Some(entry_depths);
+    }
+    entry_depths[0] = Some(initial_depth);
+    // Each block might be reached with different depths along different paths, the deepest one
+    // is kept. Like Bellman-Ford, anything still getting deeper after one round per block is
+    // going around a loop.
+    for _ in 0..=blocks.len() {
+        let mut changed = false;
+        for (index, block) in blocks.iter().enumerate() {
+            let mut depth = match entry_depths[index] {
+                Some(depth) => depth,
+                None => continue,
+            };
+            for command in &commands[block.start..block.end] {
+                depth += stack_effect(command).1;
+            }
+            for successor in &successors[index] {
+                if entry_depths[*successor].is_none_or(|previous| depth > previous) {
+                    entry_depths[*successor] = Some(depth);
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            return Some(entry_depths);
+        }
+    }
+    None
+}
+
+/// The most values a function has on the stack at once, counting its locals and the values saved
+/// by each call it makes but not what the functions it calls use. Returns None if there is no
+/// limit, because a loop keeps leaving values on the stack.
+pub fn max_stack_depth(function: &Function) -> Option<usize> {
+    let commands = &function.body;
+    let blocks = basic_blocks(commands);
+    let initial_depth = function.num_locals as isize;
+    let entry_depths = block_entry_depths(commands, &blocks, initial_depth)?;
+    let mut max = initial_depth;
+    for (block, entry_depth) in blocks.iter().zip(entry_depths) {
+        let mut depth = match entry_depth {
+            Some(depth) => depth,
+            None => continue,
+        };
+        for command in &commands[block.start..block.end] {
+            let (peak, change) = stack_effect(command);
+            max = max.max(depth + peak);
+            depth += change;
+        }
+    }
+    Some(max.max(0) as usize)
+}
+
+/// How many values are on the stack right before each `goto` and `return` in a function that can
+/// be reached, along with the index of the command in the function's body. Returns None if there
+/// is no limit to how deep the stack gets.
+pub fn terminator_depths(function: &Function) -> Option<Vec<(usize, isize)>> {
+    let commands = &function.body;
+    let blocks = basic_blocks(commands);
+    let entry_depths = block_entry_depths(commands, &blocks, function.num_locals as isize)?;
+    let mut depths = Vec::new();
+    for (block, entry_depth) in blocks.iter().zip(entry_depths) {
+        let mut depth = match entry_depth {
+            Some(depth) => depth,
+            None => continue,
+        };
+        for (index, command) in commands
+            .iter()
+            .enumerate()
+            .take(block.end)
+            .skip(block.start)
+        {
+            if command.is_terminator() {
+                depths.push((index, depth));
+            }
+            depth += stack_effect(command).1;
+        }
+    }
+    Some(depths)
+}
+
+/// The maximum stack depth of every function in a program, for `--stack-report`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StackReport {
+    /// The name of each function (None for commands before the first one) along with its maximum
+    /// stack depth, if it has one.
+    pub functions: Vec<(Option<String>, Option<usize>)>,
+}
+
+impl StackReport {
+    pub fn of_program(program: &VmProgram) -> Self {
+        let functions = program.functions().into_iter();
+        let functions = functions.map(|function| {
+            let depth = max_stack_depth(&function);
+            (function.name, depth)
+        });
+        Self {
+            functions: functions.collect(),
+        }
+    }
+}
+
+impl Display for StackReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Maximum stack depth:")?;
+        for (name, depth) in &self.functions {
+            let name = name.as_deref().unwrap_or("(top level)");
+            match depth {
+                Some(depth) => write!(f, "\n    {}: {}", name, depth)?,
+                None => write!(f, "\n    {}: unlimited", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{options::Options, test_util::parse_program};
+
+    #[test]
+    fn takes_the_deepest_path_through_a_function() {
+        let source = "function Main.f 1\npush constant 1\npush constant 2\nadd\nif-goto SKIP\n\
+            push constant 3\npush constant 4\npush constant 5\npop local 0\nlabel SKIP\n\
+            push constant 6\ncall Main.f 1\nreturn\n\
+            function Main.g 0\nlabel LOOP\npush constant 0\ngoto LOOP\n";
+        let program = parse_program(source, &Options::default());
+        let functions = program.functions();
+        // The local, the 2 values left behind when the branch isn't taken, the argument, and the
+        // 5 values saved by the call.
+        assert_eq!(max_stack_depth(&functions[0]), Some(9));
+        assert_eq!(max_stack_depth(&functions[1]), None);
+        assert_eq!(
+            StackReport::of_program(&program).to_string(),
+            "Maximum stack depth:\n    Main.f: 9\n    Main.g: unlimited"
+        );
+    }
+}
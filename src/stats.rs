@@ -0,0 +1,128 @@
+use crate::{
+    json,
+    vm_program::{VmCommand, VmProgram},
+};
+use std::fmt::{self, Display, Formatter};
+
+/// Size information about a translated program.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Statistics {
+    pub files: usize,
+    pub commands: usize,
+    pub instructions: usize,
+    pub functions: usize,
+    pub static_slots: usize,
+    /// The name of the function with the most commands along with how many it has, not counting
+    /// the `function` command itself. The first one wins if several are just as long.
+    pub largest_function: Option<(String, usize)>,
+}
+
+impl Statistics {
+    /// Collects everything except the instruction count, which is only known after translation.
+    pub fn of_program(program: &VmProgram) -> Self {
+        let functions = program.commands.iter();
+        let functions = functions.filter(|command| matches!(command, VmCommand::FnSetup { .. }));
+        let mut largest_function: Option<(String, usize)> = None;
+        for function in program.functions() {
+            let larger = largest_function
+                .as_ref()
+                .is_none_or(|(_, size)| function.body.len() > *size);
+            if let (Some(name), true) = (function.name, larger) {
+                largest_function = Some((name, function.body.len()));
+            }
+        }
+        Self {
+            files: program.files.len(),
+            commands: program.commands.len(),
+            instructions: 0,
+            functions: functions.count(),
+            static_slots: program.static_slots_used(),
+            largest_function,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let fields = [
+            ("files", self.files),
+            ("commands", self.commands),
+            ("instructions", self.instructions),
+            ("functions", self.functions),
+            ("static_slots", self.static_slots),
+        ];
+        let mut fields: Vec<_> = fields
+            .iter()
+            .map(|(name, value)| format!("{}:{}", json::string(name), value))
+            .collect();
+        let largest_function = match &self.largest_function {
+            Some((name, commands)) => format!(
+                "{{{}:{},{}:{}}}",
+                json::string("name"),
+                json::string(name),
+                json::string("commands"),
+                commands
+            ),
+            None => "null".to_owned(),
+        };
+        fields.push(format!(
+            "{}:{}",
+            json::string("largest_function"),
+            largest_function
+        ));
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+impl Display for Statistics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Files: {}", self.files)?;
+        writeln!(f, "VM commands: {}", self.commands)?;
+        writeln!(f, "Assembly instructions: {}", self.instructions)?;
+        writeln!(f, "Functions: {}", self.functions)?;
+        write!(f, "Static slots used: {}", self.static_slots)?;
+        if let Some((name, commands)) = &self.largest_function {
+            write!(f, "\nLargest function: {} ({} commands)", name, commands)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, options::Options, test_util::parse_program, translate};
+
+    #[test]
+    fn json_has_every_statistic() {
+        let options = Options::default();
+        let source = "function Main.main 0\npush static 0\npush static 1\nadd\nreturn\n";
+        let program = parse_program(source, &options);
+        let mut statistics = Statistics::of_program(&program);
+        let result = translate::translate(program, &options).unwrap();
+        statistics.instructions = assemble::count_instructions(&result);
+        assert_eq!(
+            statistics.to_json(),
+            format!(
+                "{{\"files\":1,\"commands\":6,\"instructions\":{},\"functions\":1,\
+                \"static_slots\":2,\"largest_function\":{{\"name\":\"Main.main\",\"commands\":4}}}}",
+                statistics.instructions
+            )
+        );
+        assert!(statistics.instructions > 0);
+    }
+
+    #[test]
+    fn reports_the_largest_function() {
+        let options = Options::default();
+        let source = "function Main.small 0\npush constant 0\nreturn\n\
+            function Main.big 0\npush constant 1\npush constant 2\nadd\nreturn\n\
+            function Main.same 0\npush constant 1\npush constant 2\nsub\nreturn\n";
+        let statistics = Statistics::of_program(&parse_program(source, &options));
+        assert_eq!(
+            statistics.largest_function,
+            Some(("Main.big".to_owned(), 4))
+        );
+        assert!(statistics
+            .to_string()
+            .ends_with("\nLargest function: Main.big (4 commands)"));
+    }
+}
@@ -0,0 +1,112 @@
+//! Helpers shared by the tests of the other modules.
+
+use crate::{assemble, options::Options, parse, translate, vm_program::VmProgram};
+
+/// Parses source code as if it came from a file called `Test.vm`.
+pub fn parse_program(source: &str, options: &Options) -> VmProgram {
+    let mut program = VmProgram::new();
+    parse::parse(&mut program, source, "Test.vm", options).unwrap();
+    program
+}
+
+pub fn translate_source(source: &str, options: &Options) -> String {
+    translate::translate(parse_program(source, options), options).unwrap()
+}
+
+/// A Hack computer, for checking what translated code actually does when it runs.
+pub struct Computer {
+    pub ram: Vec<u16>,
+    pub a: u16,
+    pub d: u16,
+    pub pc: usize,
+    rom: Vec<u16>,
+}
+
+impl Computer {
+    /// Assembles `source` and loads it into ROM. With no bootstrap the stack pointer has to be
+    /// set up by hand before running.
+    pub fn new(source: &str) -> Self {
+        Self {
+            ram: vec![0; 32768],
+            a: 0,
+            d: 0,
+            pc: 0,
+            rom: assemble::assemble(source).unwrap(),
+        }
+    }
+
+    /// Runs until the program falls off the end of ROM or `max_steps` instructions have run.
+    pub fn run(&mut self, max_steps: usize) {
+        for _ in 0..max_steps {
+            if self.pc >= self.rom.len() {
+                return;
+            }
+            self.step();
+        }
+    }
+
+    /// Runs instructions until the next one to run is at `address`.
+    pub fn run_until(&mut self, address: usize, max_steps: usize) {
+        for _ in 0..max_steps {
+            if self.pc == address {
+                return;
+            }
+            self.step();
+        }
+        panic!("Never reached address {}.", address);
+    }
+
+    /// The address the label `(name)` in some assembly ends up at.
+    pub fn label_address(source: &str, name: &str) -> usize {
+        let end = source.find(&format!("({})\n", name)).unwrap();
+        assemble::count_instructions(&source[..end])
+    }
+
+    pub fn step(&mut self) {
+        let instruction = self.rom[self.pc];
+        self.pc += 1;
+        if instruction & 0x8000 == 0 {
+            self.a = instruction;
+            return;
+        }
+        let bit = |index: u16| instruction & (1 << index) != 0;
+        let address = self.a;
+        let mut x = self.d;
+        let mut y = if bit(12) {
+            self.ram[address as usize & 0x7FFF]
+        } else {
+            self.a
+        };
+        if bit(11) {
+            x = 0;
+        }
+        if bit(10) {
+            x = !x;
+        }
+        if bit(9) {
+            y = 0;
+        }
+        if bit(8) {
+            y = !y;
+        }
+        let mut out = if bit(7) { x.wrapping_add(y) } else { x & y };
+        if bit(6) {
+            out = !out;
+        }
+        // Memory is written, and jumps go, to the address from before this instruction changes A.
+        if bit(3) {
+            self.ram[address as usize & 0x7FFF] = out;
+        }
+        if bit(5) {
+            self.a = out;
+        }
+        if bit(4) {
+            self.d = out;
+        }
+        let signed = out as i16;
+        let jump = (bit(2) && signed < 0) || (bit(1) && signed == 0) || (bit(0) && signed > 0);
+        if jump {
+            self.pc = address as usize;
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Describes what each command does to the stack, for showing how the VM works in class. This is
+//! worked out without running the program, so values that depend on memory or on which way a jump
+//! went are written as where they came from, or `?`.
+
+use crate::vm_program::{ArithmeticOpcode, VmCommand, VmProgram};
+
+/// A value on the stack, as far as we can tell without running the program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Value {
+    Known(i16),
+    /// Where the value came from, like `local 0`.
+    Unknown(String),
+}
+
+impl Value {
+    fn describe(&self) -> String {
+        match self {
+            Self::Known(value) => value.to_string(),
+            Self::Unknown(source) => source.clone(),
+        }
+    }
+}
+
+fn describe_all(values: &[Value]) -> String {
+    let values: Vec<_> = values.iter().map(Value::describe).collect();
+    values.join(", ")
+}
+
+fn evaluate(opcode: ArithmeticOpcode, a: i16, b: i16) -> i16 {
+    use ArithmeticOpcode::*;
+    let boolean = |value: bool| if value { -1 } else { 0 };
+    match opcode {
+        Add => a.wrapping_add(b),
+        Sub => a.wrapping_sub(b),
+        Neg => a.wrapping_neg(),
+        Eq => boolean(a == b),
+        Gt => boolean(a > b),
+        Lt => boolean(a < b),
+        And => a & b,
+        Or => a | b,
+        Not => !a,
+    }
+}
+
+/// Keeps track of the stack while going through the commands in order.
+#[derive(Default)]
+struct Tracer {
+    stack: Vec<Value>,
+}
+
+impl Tracer {
+    fn pop(&mut self) -> Value {
+        self.stack
+            .pop()
+            .unwrap_or_else(|| Value::Unknown("?".to_owned()))
+    }
+
+    /// Takes `count` values off the stack, returning them in the order they were pushed.
+    fn pop_many(&mut self, count: usize) -> Vec<Value> {
+        let mut values: Vec<_> = (0..count).map(|_| self.pop()).collect();
+        values.reverse();
+        values
+    }
+
+    /// Updates the stack for a command, describing what happened to it if it was touched.
+    fn trace_command(&mut self, command: &VmCommand) -> Option<String> {
+        let effect = match command {
+            VmCommand::Push(segment, index) => {
+                let value = match segment.name() {
+                    Some("constant") => Value::Known(*index as i16),
+                    _ => Value::Unknown(format!("{}", command).replacen("push ", "", 1)),
+                };
+                self.stack.push(value);
+                None
+            }
+            VmCommand::Pop(..) | VmCommand::IfGoto(_) => {
+                Some(format!("pops {}", self.pop().describe()))
+            }
+            VmCommand::Arithmetic(opcode) => {
+                let unary = matches!(opcode, ArithmeticOpcode::Neg | ArithmeticOpcode::Not);
+                let operands = self.pop_many(if unary { 1 } else { 2 });
+                let result = match (&operands[0], operands.get(1)) {
+                    (Value::Known(a), None) => Value::Known(evaluate(*opcode, *a, 0)),
+                    (Value::Known(a), Some(Value::Known(b))) => {
+                        Value::Known(evaluate(*opcode, *a, *b))
+                    }
+                    _ => Value::Unknown("?".to_owned()),
+                };
+                let effect = format!(
+                    "pops {}, pushes {}",
+                    describe_all(&operands),
+                    result.describe()
+                );
+                self.stack.push(result);
+                Some(effect)
+            }
+            VmCommand::Call { fn_name, num_args } => {
+                let arguments = self.pop_many(*num_args);
+                let effect = if arguments.is_empty() {
+                    format!("pushes {}()", fn_name)
+                } else {
+                    format!("pops {}, pushes {}()", describe_all(&arguments), fn_name)
+                };
+                self.stack.push(Value::Unknown(format!("{}()", fn_name)));
+                Some(effect)
+            }
+            VmCommand::Return => {
+                let value = self.pop();
+                self.stack.clear();
+                return Some(format!("returns {}", value.describe()));
+            }
+            VmCommand::FnSetup { .. } => {
+                self.stack.clear();
+                return Some("starts with an empty stack".to_owned());
+            }
+            VmCommand::Label(_) | VmCommand::Goto(_) | VmCommand::Nop => return None,
+            VmCommand::RawAsm(_) => return Some("might do anything to the stack".to_owned()),
+        };
+        let stack = format!("[{}]", describe_all(&self.stack));
+        Some(match effect {
+            Some(effect) => format!("{} ({})", stack, effect),
+            None => stack,
+        })
+    }
+}
+
+/// Describes each command of a program on its own line, like `add → [7] (pops 3, 4, pushes 7)`
+/// with the stack after the command and what the command did to it. A function is shown the way it
+/// is written in a .vm file, as one line with its name and number of locals.
+pub fn trace(program: &VmProgram) -> String {
+    let mut tracer = Tracer::default();
+    let mut result = String::new();
+    let mut commands = program.commands.iter().peekable();
+    while let Some(mut command) = commands.next() {
+        let mut text = command.to_string();
+        if let (VmCommand::Label(name), Some(VmCommand::FnSetup { num_locals })) =
+            (command, commands.peek())
+        {
+            text = format!("function {} {}", name, num_locals);
+            command = commands.next().unwrap();
+        }
+        match tracer.trace_command(command) {
+            Some(effect) => result.push_str(&format!("{} → {}\n", text, effect)),
+            None => result.push_str(&format!("{}\n", text)),
+        }
+        // Nothing is known about the stack after a jump, since execution continues from
+        // somewhere else.
+        if command.is_terminator() {
+            tracer.stack.clear();
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{options::Options, test_util::parse_program};
+
+    #[test]
+    fn describes_what_each_command_does() {
+        let source = "function Main.main 0\npush constant 3\npush constant 4\nadd\n\
+            push local 0\nlt\npop temp 0\ncall Math.abs 0\npush constant 1\ncall Math.max 2\n\
+            return\n";
+        let program = parse_program(source, &Options::default());
+        assert_eq!(
+            trace(&program),
+            "function Main.main 0 → starts with an empty stack\n\
+            push constant 3 → [3]\n\
+            push constant 4 → [3, 4]\n\
+            add → [7] (pops 3, 4, pushes 7)\n\
+            push local 0 → [7, local 0]\n\
+            lt → [?] (pops 7, local 0, pushes ?)\n\
+            pop temp 0 → [] (pops ?)\n\
+            call Math.abs 0 → [Math.abs()] (pushes Math.abs())\n\
+            push constant 1 → [Math.abs(), 1]\n\
+            call Math.max 2 → [Math.max()] (pops Math.abs(), 1, pushes Math.max())\n\
+            return → returns Math.max()\n"
+        );
+    }
+}
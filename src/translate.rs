@@ -1,7 +1,19 @@
-use crate::vm_program::{ArithmeticOpcode, MemorySegment, VmCommand, VmProgram};
+use crate::{
+    assemble::strip_comment,
+    error::CompileError,
+    layout::{STACK_MEMORY_START, STATIC_MEMORY_START, TEMP_START, THIS},
+    options::{AssemblyDialect, Options},
+    vm_program::{
+        demangle_identifier, sanitize_identifier, AddressingMode, ArithmeticOpcode, MemorySegment,
+        VmCommand, VmProgram,
+    },
+};
 use std::{
+    collections::{HashMap, HashSet},
     error::Error,
     fmt::{Display, Formatter},
+    ops::Range,
+    path::Path,
 };
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -24,18 +36,89 @@ impl Display for Register {
 
 use Register::*;
 
-struct Translator {
+/// The start of the name of every label the translator makes up.
+const INTERNAL_LABEL_PREFIX: &str = "__VM_IMPL_LABEL_";
+/// About how long the code (including comments) for each instruction is, used to guess how big
+/// the output will be.
+const BYTES_PER_INSTRUCTION: usize = 32;
+/// The label of the subroutine every comparison jumps to when `Options::shared_comparisons` is set.
+const COMPARE_LABEL: &str = "__VM_IMPL_LABEL_COMPARE";
+/// Where the program gets stuck if `Options::debug_asserts` catches a problem.
+const TRAP_LABEL: &str = "__VM_TRAP";
+/// Functions with more locals than this initialize them with a loop instead of one push each.
+pub(crate) const MAX_UNROLLED_LOCALS: usize = 4;
+/// How many `=` the lines of the separator `Options::section_per_function` puts before functions
+/// are made of.
+const SECTION_SEPARATOR_WIDTH: usize = 77;
+
+/// Called with each command and the range of bytes in the output its code takes up.
+pub type Callback<'a> = dyn FnMut(&VmCommand, Range<usize>) + 'a;
+
+struct Translator<'a> {
     /// The VM creates its own labels for some commands, this keeps track of a counter that
-    /// ensures the label names are unique.
+    /// ensures the label names are unique. Not used for commands with `label_base`.
     next_unnamed_label_id: usize,
     result: String,
-    /// Used to determine how many locals should be popped when a return command is encountered.
-    current_num_locals: usize,
+    /// How many locals the function we are currently in has, only used for comments since returns
+    /// deallocate locals using LCL. None when outside of any function.
+    current_num_locals: Option<usize>,
+    /// The file the previous command came from, since functions end along with their file.
+    current_file: Option<usize>,
+    /// Used to give every command a unique number when `Options::trace_comments` is set.
+    next_trace_id: usize,
+    /// Names for the locals of the function about to be set up, from its `CommandInfo`.
+    local_names: Option<Vec<String>>,
+    /// How many instructions are in `result` up to `counted_bytes`, so call sites of the shared
+    /// comparison subroutine can work out their own address.
+    instruction_count: usize,
+    counted_bytes: usize,
+    /// With `Options::deterministic_labels`, what labels made for the current command are named
+    /// after, along with how many labels have been given each name so far.
+    label_base: Option<String>,
+    label_bases_used: HashMap<String, usize>,
+    /// For `Options::file_index`, every file the commands switch to along with where in `result`
+    /// its code starts.
+    file_starts: Vec<(usize, usize)>,
+    /// Told about the code each command turned into once the whole program is translated, for
+    /// `translate_with_callback`. The commands are only kept in `command_ranges` if this is set.
+    callback: Option<&'a mut Callback<'a>>,
+    command_ranges: Vec<(VmCommand, Range<usize>)>,
+    options: &'a Options,
 }
 
-impl Translator {
+impl<'a> Translator<'a> {
+    fn new(options: &'a Options) -> Self {
+        Self {
+            next_unnamed_label_id: 0,
+            result: String::new(),
+            current_num_locals: None,
+            current_file: None,
+            next_trace_id: 0,
+            local_names: None,
+            instruction_count: 0,
+            counted_bytes: 0,
+            label_base: None,
+            label_bases_used: HashMap::new(),
+            file_starts: Vec::new(),
+            callback: None,
+            command_ranges: Vec::new(),
+            options,
+        }
+    }
+
     fn make_label(&mut self) -> String {
-        let label = format!("__VM_IMPL_LABEL_{}", self.next_unnamed_label_id);
+        if let Some(base) = &self.label_base {
+            // `$` can't appear in a file name after sanitizing it, so these never collide with
+            // each other or with the numbered labels.
+            let used = self.label_bases_used.entry(base.clone()).or_insert(0);
+            let label = match *used {
+                0 => format!("{}{}", INTERNAL_LABEL_PREFIX, base),
+                n => format!("{}{}${}", INTERNAL_LABEL_PREFIX, base, n),
+            };
+            *used += 1;
+            return label;
+        }
+        let label = format!("{}{}", INTERNAL_LABEL_PREFIX, self.next_unnamed_label_id);
         self.next_unnamed_label_id += 1;
         label
     }
@@ -75,22 +158,161 @@ M=M-1    // load *spa-1 into *spa
         ));
     }
 
-    fn translate_arithmetic_opcode(&mut self, opcode: ArithmeticOpcode) {
-        self.result.push_str("// command: arithmetic\n");
-        use ArithmeticOpcode::*;
-        let mut pop_second = true;
-        let op = match opcode {
-            Add => "M=M+D",
-            Sub => "M=M-D",
-            Neg => {
-                pop_second = false;
-                "M=-M"
-            }
-            Eq | Gt | Lt => {
-                self.pop(D);
-                let skip_set_false = self.make_label();
-                self.result.push_str(&format!(
-                    r"@SP      // Load spa into A
+    /// The address the next instruction added to `result` will end up at, taking
+    /// `Options::relocate` into account.
+    fn next_address(&mut self) -> usize {
+        let new_code = &self.result[self.counted_bytes..];
+        self.instruction_count += new_code
+            .lines()
+            .map(strip_comment)
+            .filter(|code| !code.is_empty() && !code.starts_with('('))
+            .count();
+        self.counted_bytes = self.result.len();
+        self.options.relocate + self.instruction_count
+    }
+
+    /// The subroutine shared by every comparison. It expects which comparison to do in R15 (0 for
+    /// eq, 1 for gt, 2 for lt) and the address to return to in D, and replaces the two operands on
+    /// the stack with the result. It goes at the very start of the program, so the code before it
+    /// jumps over it. Return addresses are absolute, so the output only works loaded at
+    /// `Options::relocate`.
+    fn translate_compare_subroutine(&mut self) {
+        self.result.push_str(&format!(
+            r"// Shared comparison subroutine
+@{0}_END
+0;JEQ
+({0})
+@R14
+M=D      // save the return address
+{2}@SP
+AM=M-1   // pop the second operand
+D=M
+A=A-1
+D=M-D    // find the difference between the operands
+@R13
+M=D
+@R15
+D=M      // load which comparison to do into D
+@{0}_GT_OR_LT
+D;JNE
+@R13
+D=M      // load the difference into D
+@{0}_TRUE
+D;JEQ
+@{0}_FALSE
+0;JEQ
+({0}_GT_OR_LT)
+D=D-1
+@{0}_LT
+D;JNE
+@R13
+D=M
+@{0}_TRUE
+D;JGT
+@{0}_FALSE
+0;JEQ
+({0}_LT)
+@R13
+D=M
+@{0}_TRUE
+D;JLT
+({0}_FALSE)
+@SP
+A=M-1
+M=0      // load false into *(*spa-1)
+@R14
+A=M
+0;JEQ
+({0}_TRUE)
+@SP
+A=M-1
+M=-1     // load true into *(*spa-1)
+{1}@R14
+A=M
+0;JEQ
+({0}_END)
+
+",
+            COMPARE_LABEL,
+            self.narrow_top(),
+            self.offset_operands()
+        ));
+    }
+
+    /// When `Options::word_bits` is less than 16, values only use the lowest bits of the 16 bit
+    /// ALU's words, so the bits above them have to be cleared after anything that can set them,
+    /// like true being -1. This does that to the value on top of the stack.
+    fn narrow_top(&self) -> String {
+        if self.options.word_bits >= 16 {
+            return String::new();
+        }
+        format!(
+            "// keep only the lowest {} bits of the result\n@{}\nD=A\n@SP\nA=M-1\nM=D&M\n",
+            self.options.word_bits,
+            self.options.true_value()
+        )
+    }
+
+    /// A narrow word with its top bit set is negative, but looks positive to the 16 bit ALU. When
+    /// `Options::word_bits` is less than 16, this adds half of the word's range to the two values
+    /// on top of the stack and narrows them again, which puts them in the same order as unsigned
+    /// numbers. Subtracting them to compare them then never overflows, since the difference
+    /// always fits in 16 bits.
+    fn offset_operands(&self) -> String {
+        if self.options.word_bits >= 16 {
+            return String::new();
+        }
+        format!(
+            r"// compare the operands as {} bit words
+@{}
+D=A
+@SP
+A=M-1
+M=D+M
+A=A-1
+M=D+M
+@{}
+D=A
+@SP
+A=M-1
+M=D&M
+A=A-1
+M=D&M
+",
+            self.options.word_bits,
+            self.options.max_constant() + 1,
+            self.options.true_value()
+        )
+    }
+
+    /// Shared by eq, gt, and lt, which only differ by the jump used to test the difference of the
+    /// two operands.
+    fn translate_comparison(&mut self, jump: &str) {
+        if self.options.shared_comparisons {
+            // The subroutine offsets the operands itself.
+            let kind = match jump {
+                "JEQ" => 0,
+                "JGT" => 1,
+                _ => 2,
+            };
+            self.result.push_str(&format!(
+                "@{}\nD=A\n@R15\nM=D      // load which comparison to do into R15\n",
+                kind
+            ));
+            // The return address is right after the jump at the end of this command.
+            let return_address = self.next_address() + 4;
+            self.result.push_str(&format!(
+                "@{}\nD=A      // load the return address into D\n@{}\n0;JEQ\n// end command: arithmetic\n\n",
+                return_address, COMPARE_LABEL
+            ));
+            return;
+        }
+        let offset = self.offset_operands();
+        self.result.push_str(&offset);
+        self.pop(D);
+        let skip_set_false = self.make_label();
+        self.result.push_str(&format!(
+            r"@SP      // Load spa into A
 A=M-1    // load *spa-1 into A
 D=M-D    // perform comparison between D and *(*spa-1)
 M=-1     // load true into *(*spa-1)
@@ -100,19 +322,59 @@ D;{1}    // skip setting value to false if condition is true
 A=M-1    // load *spa-1 into A
 M=0      // load false into *(*spa-1)
 ({0})
-// end command: arithmetic
+{2}// end command: arithmetic
 
 ",
-                    skip_set_false,
-                    match opcode {
-                        Eq => "JEQ",
-                        Gt => "JGT",
-                        Lt => "JLT",
-                        _ => unreachable!(),
-                    }
-                ));
-                return;
+            skip_set_false,
+            jump,
+            self.narrow_top()
+        ));
+    }
+
+    /// The separator `Options::section_per_function` puts before each function.
+    fn translate_section_separator(&mut self, fn_name: &str) {
+        let line = "=".repeat(SECTION_SEPARATOR_WIDTH);
+        self.result.push_str(&format!(
+            "\n// {0}\n// function {1}\n// {0}\n",
+            line, fn_name
+        ));
+    }
+
+    /// A comparison immediately followed by `if-goto label`, for `Options::fuse_branches`. The
+    /// jump is taken in exactly the cases the comparison would have pushed true.
+    fn translate_fused_branch(&mut self, jump: &str, label: &str) {
+        self.result
+            .push_str("// command: arithmetic\n// fused with the if-goto after it\n");
+        let offset = self.offset_operands();
+        self.result.push_str(&offset);
+        self.pop(D);
+        self.result.push_str(&format!(
+            r"@SP      // load spa into A
+AM=M-1   // pop the first operand, loading its address into A
+D=M-D    // perform comparison between D and the first operand
+// command: if-goto {0}
+@{0}
+D;{1}
+",
+            label, jump
+        ));
+    }
+
+    fn translate_arithmetic_opcode(&mut self, opcode: ArithmeticOpcode) {
+        self.result.push_str("// command: arithmetic\n");
+        use ArithmeticOpcode::*;
+        let mut pop_second = true;
+        let op = match opcode {
+            Add => "M=M+D",
+            Sub => "M=M-D",
+            Neg => {
+                pop_second = false;
+                // The ALU negates in two's complement and wraps at 16 bits, so 32767 becomes
+                // -32767 (0x8001) and -32768 stays as it is, the same as any other Hack program.
+                // Narrower words wrap the same way once the result is narrowed.
+                "M=-M"
             }
+            Eq | Gt | Lt => return self.translate_comparison(comparison_jump(opcode).unwrap()),
             And => "M=M&D",
             Or => "M=M|D",
             Not => {
@@ -123,14 +385,19 @@ M=0      // load false into *(*spa-1)
         if pop_second {
             self.pop(D);
         }
+        // And and or can't set bits that weren't set in their operands.
+        let narrow = match opcode {
+            And | Or => String::new(),
+            _ => self.narrow_top(),
+        };
         self.result.push_str(&format!(
             r"@SP      // Load spa into A
 A=M-1    // Load *spa-1 into A
 {}
-// end command: arithmetic
+{}// end command: arithmetic
 
 ",
-            op
+            op, narrow
         ))
     }
 
@@ -169,6 +436,9 @@ A=M-1    // Load *spa-1 into A
         self.push(M);
         self.result.push_str("// push old THAT onto stack\n@THAT\n");
         self.push(M);
+        // The arguments are below the five values we just pushed. With no arguments, ARG ends up
+        // pointing at the return address, which is fine because return saves it before putting
+        // the return value at *ARG.
         self.result.push_str(&format!(
             r"// create new ARG pointer
 @{0} 
@@ -195,23 +465,60 @@ M=D
     }
 
     fn translate_fn_setup(&mut self, num_locals: usize) {
-        self.current_num_locals = num_locals;
+        self.current_num_locals = Some(num_locals);
         self.result
             .push_str(&format!("// command: function {}\n", num_locals));
-        for idx in 0..num_locals {
-            self.result
-                .push_str(&format!("// push local #{}\nD=0\n", idx));
-            self.push(D);
+        // Names that don't match up with the locals are probably out of date, so ignore them.
+        match self.local_names.take() {
+            Some(names) if names.len() == num_locals => {
+                for (index, name) in names.iter().enumerate() {
+                    self.result
+                        .push_str(&format!("// local {} = {}\n", index, name));
+                }
+            }
+            _ => (),
+        }
+        if num_locals > MAX_UNROLLED_LOCALS {
+            // Unrolling this many pushes would make a lot of code, loop instead.
+            let loop_label = self.make_label();
+            self.result.push_str(&format!(
+                r"// push {0} locals in a loop
+@{0}
+D=A
+@R13     // R13 counts how many locals are left
+M=D
+({1})
+@SP
+A=M
+M=0      // load 0 into **spa
+@SP
+M=M+1    // increment *spa
+@R13
+MD=M-1   // decrement the counter
+@{1}
+D;JGT    // keep going until there are no locals left
+",
+                num_locals, loop_label
+            ));
+        } else {
+            for idx in 0..num_locals {
+                self.result
+                    .push_str(&format!("// push local #{}\nD=0\n", idx));
+                self.push(D);
+            }
         }
         self.result
             .push_str(&format!("// end command: function {}\n\n", num_locals));
     }
 
     fn translate_return(&mut self) {
-        self.result.push_str(&format!(
-            "// command: return ({0} locals)\n// pop return value\n",
-            self.current_num_locals
-        ));
+        match self.current_num_locals {
+            Some(num_locals) => self
+                .result
+                .push_str(&format!("// command: return ({0} locals)\n", num_locals)),
+            None => self.result.push_str("// command: return\n"),
+        }
+        self.result.push_str("// pop return value\n");
         self.pop(D);
         self.result.push_str("// store in R14\n@R14\nM=D\n");
         self.result.push_str(
@@ -271,79 +578,1075 @@ M=D
         )
     }
 
-    fn translate_push(&mut self, segment: MemorySegment, index: usize) {
-        use MemorySegment::*;
-        let code = match segment {
-            Constant => format!("@{}\nD=A", index),
-            Local => Self::load_d_from_ptr_offset("LCL", index),
-            Argument => Self::load_d_from_ptr_offset("ARG", index),
-            This => Self::load_d_from_ptr_offset("THIS", index),
-            That => Self::load_d_from_ptr_offset("THAT", index),
-            Pointer => Self::load_d_from_offset(3 + index),
-            Temp => Self::load_d_from_offset(5 + index),
+    /// Where the first element of a segment with fixed addressing is.
+    fn fixed_segment_start(segment: MemorySegment) -> usize {
+        match segment {
+            MemorySegment::Pointer => THIS,
+            MemorySegment::Temp => TEMP_START,
             // The index of static push/pops is modified by the parser so that they are
             // globally unique. We do not have to worry about what file the command came from.
-            Static => Self::load_d_from_offset(16 + index),
+            MemorySegment::Static => STATIC_MEMORY_START,
+            _ => unreachable!(),
+        }
+    }
+
+    fn translate_push(&mut self, segment: MemorySegment, index: usize) {
+        let code = match (segment.addressing_mode(), segment.pointer_symbol()) {
+            (AddressingMode::Immediate, _) => format!("@{}\nD=A", index),
+            (_, Some(pointer)) => Self::load_d_from_ptr_offset(pointer, index),
+            _ => Self::load_d_from_offset(Self::fixed_segment_start(segment) + index),
         };
         self.result
             .push_str(&format!("// command: push {:?} {}\n", segment, index));
         self.result.push_str(&code);
-        self.push(D);
+        if segment.addressing_mode() == AddressingMode::Fixed {
+            // A is free to use for the stack pointer once D has the value, so there is no need to
+            // go through D for it.
+            self.result
+                .push_str("// action: push\n@SP\nM=M+1\nA=M-1\nM=D\n");
+        } else {
+            self.push(D);
+        }
         self.result.push_str("// end command: push\n\n");
     }
 
-    fn translate_pop(&mut self, segment: MemorySegment, index: usize) {
-        use MemorySegment::*;
-        let code = match segment {
-            Constant => unreachable!(),
-            Local => Self::store_d_into_ptr_offset("LCL", index),
-            Argument => Self::store_d_into_ptr_offset("ARG", index),
-            This => Self::store_d_into_ptr_offset("THIS", index),
-            That => Self::store_d_into_ptr_offset("THAT", index),
-            Pointer => Self::store_d_into_offset(3 + index),
-            Temp => Self::store_d_into_offset(5 + index),
-            // The index of static push/pops is modified by the parser so that they are
-            // globally unique. We do not have to worry about what file the command came from.
-            Static => Self::store_d_into_offset(16 + index),
+    fn translate_pop(&mut self, segment: MemorySegment, index: usize) -> Result<(), String> {
+        let code = match (segment.addressing_mode(), segment.pointer_symbol()) {
+            // The parser doesn't allow this, but a program could have been put together by hand.
+            (AddressingMode::Immediate, _) => {
+                return Err(format!(
+                    "Cannot translate \"pop {:?} {}\", there is nowhere to pop the value into.",
+                    segment, index
+                ))
+            }
+            (_, Some(pointer)) => Self::store_d_into_ptr_offset(pointer, index),
+            _ => Self::store_d_into_offset(Self::fixed_segment_start(segment) + index),
         };
         self.result
             .push_str(&format!("// command: pop {:?} {}\n", segment, index));
-        self.pop(D);
+        if segment.addressing_mode() == AddressingMode::Fixed {
+            // The address is known ahead of time, so nothing needs to be kept in R13 while it is
+            // worked out.
+            self.result.push_str("// action: pop\n@SP\nAM=M-1\nD=M\n");
+        } else {
+            self.pop(D);
+        }
         self.result.push_str(&code);
+        Ok(())
+    }
+
+    /// For `Options::debug_asserts`, jumps to the trap if the stack has fewer than `count` values
+    /// on it.
+    fn assert_stack_has(&mut self, count: usize) {
+        self.result.push_str(&format!(
+            "// assert: the stack has at least {0} values\n@SP\nD=M\n@{1}\nD=D-A\n@{2}\nD;JLT\n",
+            count,
+            STACK_MEMORY_START + count,
+            TRAP_LABEL
+        ));
+    }
+
+    /// Fails with a description of the problem if the command can't be translated.
+    fn translate_command(&mut self, command: VmCommand) -> Result<(), String> {
+        if self.options.debug_asserts {
+            use ArithmeticOpcode::*;
+            match &command {
+                VmCommand::Arithmetic(Neg) | VmCommand::Arithmetic(Not) => self.assert_stack_has(1),
+                VmCommand::Arithmetic(_) => self.assert_stack_has(2),
+                VmCommand::Pop(..) | VmCommand::IfGoto(_) => self.assert_stack_has(1),
+                _ => (),
+            }
+        }
+        match command {
+            VmCommand::Arithmetic(opcode) => self.translate_arithmetic_opcode(opcode),
+            VmCommand::Call { fn_name, num_args } => self.translate_call(fn_name, num_args),
+            VmCommand::FnSetup { num_locals } => self.translate_fn_setup(num_locals),
+            VmCommand::Goto(label) => self.result.push_str(&format!("@{}\n0;JEQ\n", label)),
+            VmCommand::IfGoto(label) => {
+                self.pop(D);
+                self.result.push_str(&format!("@{}\nD;JNE\n", label));
+            }
+            VmCommand::Label(label) => {
+                // Keep the original name of quoted identifiers around for readability.
+                if let Some(original) = demangle_identifier(&label) {
+                    self.result
+                        .push_str(&format!("// label \"{}\"\n", original));
+                }
+                self.result.push_str(&format!("({})\n", label))
+            }
+            VmCommand::Push(segment, index) => self.translate_push(segment, index),
+            VmCommand::Pop(segment, index) => self.translate_pop(segment, index)?,
+            VmCommand::Return => self.translate_return(),
+            VmCommand::Nop => self.result.push_str("// command: nop\n"),
+            VmCommand::RawAsm(code) => {
+                self.result.push_str("// command: raw assembly\n");
+                self.result.push_str(&code);
+                self.result.push('\n');
+            }
+        };
+        Ok(())
     }
 
-    fn translate(mut self, commands: Vec<VmCommand>) -> String {
-        if commands.contains(&VmCommand::Label(format!("Sys.init"))) {
+    /// The comment block `Options::file_index` puts at the start of the output, listing the lines
+    /// the code for each file takes up. Lines are counted from the start of the output including
+    /// this block. Files that come up more than once, for example because of inlining, get a line
+    /// for each time.
+    fn file_index(&self, files: &[String], code_end: usize) -> String {
+        let header_lines = self.file_starts.len() + 2;
+        let mut ends = self.file_starts.iter().skip(1).map(|(_, start)| *start);
+        let mut index = String::from("// Files:\n");
+        let mut line = header_lines + 1;
+        let mut counted = 0;
+        for (file, start) in &self.file_starts {
+            line += self.result[counted..*start].matches('\n').count();
+            let end = ends.next().unwrap_or(code_end);
+            let last_line = line + self.result[*start..end].matches('\n').count() - 1;
+            index.push_str(&format!(
+                "//     {}: lines {}-{}\n",
+                files[*file], line, last_line
+            ));
+            counted = *start;
+        }
+        index.push('\n');
+        index
+    }
+
+    /// Translates the whole program, leaving the assembly in `result`.
+    fn translate(&mut self, mut program: VmProgram) -> Result<(), Box<dyn Error>> {
+        let estimate = program.estimated_instruction_count();
+        self.result.reserve(estimate * BYTES_PER_INSTRUCTION);
+        let compares = |command: &VmCommand| {
+            use ArithmeticOpcode::*;
+            matches!(command, VmCommand::Arithmetic(Eq | Gt | Lt))
+        };
+        if self.options.shared_comparisons && program.commands.iter().any(compares) {
+            self.translate_compare_subroutine();
+        }
+        if has_bootstrap(&program.commands, self.options) {
             // Bootstrap
-            self.result.push_str("// Bootstrap\n@256\nD=A\n@SP\nM=D\n");
-            self.translate_call(format!("Sys.init"), 0);
+            self.result.push_str(&format!(
+                "// Bootstrap\n@{}\nD=A\n@SP\nM=D\n",
+                STACK_MEMORY_START
+            ));
+            self.translate_call(self.options.entry_function().to_owned(), 0);
         }
         // Real code
-        for command in commands {
-            match command {
-                VmCommand::Arithmetic(opcode) => self.translate_arithmetic_opcode(opcode),
-                VmCommand::Call { fn_name, num_args } => self.translate_call(fn_name, num_args),
-                VmCommand::FnSetup { num_locals } => self.translate_fn_setup(num_locals),
-                VmCommand::Goto(label) => self.result.push_str(&format!("@{}\n0;JEQ\n", label)),
-                VmCommand::IfGoto(label) => {
-                    self.pop(D);
-                    self.result.push_str(&format!("@{}\nD;JNE\n", label));
+        let commands = std::mem::take(&mut program.commands);
+        let mut commands = commands.into_iter().enumerate().peekable();
+        while let Some((index, command)) = commands.next() {
+            let info = &mut program.info[index];
+            let file = info.position.as_ref().map(|position| position.file);
+            if file != self.current_file {
+                self.current_num_locals = None;
+                self.current_file = file;
+                if let Some(file) = file {
+                    self.file_starts.push((file, self.result.len()));
+                }
+            }
+            if let (VmCommand::Label(name), Some((_, VmCommand::FnSetup { .. }))) =
+                (&command, commands.peek())
+            {
+                if self.options.section_per_function {
+                    self.translate_section_separator(name);
+                }
+            }
+            let start = self.result.len();
+            if self.options.trace_comments {
+                self.result
+                    .push_str(&format!("// TRACE {}\n", self.next_trace_id));
+                self.next_trace_id += 1;
+            }
+            if let Some(source) = info.source_annotation.take() {
+                self.result.push_str(&format!("// @src {}\n", source));
+            }
+            self.local_names = info.local_names.take();
+            if self.options.deterministic_labels {
+                let files = &program.files;
+                self.label_base = info.position.as_ref().map(|position| {
+                    let path = Path::new(&files[position.file]);
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    format!("{}${}", sanitize_identifier(&name), position.line)
+                });
+            }
+            // The if-goto can't be fused if it has something of its own to show before its code.
+            let jump = match (&command, commands.peek()) {
+                (VmCommand::Arithmetic(opcode), Some((next, VmCommand::IfGoto(_))))
+                    if self.options.fuse_branches
+                        && program.info[*next].source_annotation.is_none()
+                        && program.info[*next].position.as_ref().map(|pos| pos.file) == file =>
+                {
+                    comparison_jump(*opcode)
                 }
-                VmCommand::Label(label) => self.result.push_str(&format!("({})\n", label)),
-                VmCommand::Push(segment, index) => self.translate_push(segment, index),
-                VmCommand::Pop(segment, index) => self.translate_pop(segment, index),
-                VmCommand::Return => self.translate_return(),
+                _ => None,
             };
+            if let (Some(jump), Some((_, VmCommand::IfGoto(label)))) = (jump, commands.peek()) {
+                if self.options.debug_asserts {
+                    self.assert_stack_has(2);
+                }
+                let label = label.clone();
+                let (_, if_goto) = commands.next().unwrap();
+                // Keep the numbers lined up with the commands they would have been for.
+                self.next_trace_id += 1;
+                self.translate_fused_branch(jump, &label);
+                if self.callback.is_some() {
+                    // The comparison gets all of the code, leaving nothing for the if-goto.
+                    let end = self.result.len();
+                    self.command_ranges.push((command, start..end));
+                    self.command_ranges.push((if_goto, end..end));
+                }
+                continue;
+            }
+            let observed = self.callback.as_ref().map(|_| command.clone());
+            if let Err(message) = self.translate_command(command) {
+                let error = match program.location(index) {
+                    Some(mut location) => {
+                        location.col = location.col - 1 + self.options.column_base;
+                        CompileError::at(message, location)
+                    }
+                    None => CompileError::new(message),
+                };
+                return Err(error.into());
+            }
+            if let Some(command) = observed {
+                self.command_ranges
+                    .push((command, start..self.result.len()));
+            }
         }
-        self.result
+        let code_end = self.result.len();
+        if self.options.debug_asserts {
+            self.result.push_str(&format!(
+                "// Stack underflow trap\n({0})\n@{0}\n0;JEQ\n",
+                TRAP_LABEL
+            ));
+        }
+        if self.options.file_index {
+            let index = self.file_index(&program.files, code_end);
+            self.result.insert_str(0, &index);
+            for (_, range) in &mut self.command_ranges {
+                *range = range.start + index.len()..range.end + index.len();
+            }
+        }
+        if let Some(callback) = self.callback.take() {
+            for (command, range) in &self.command_ranges {
+                callback(command, range.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The jump that is taken when a comparison would push true.
+fn comparison_jump(opcode: ArithmeticOpcode) -> Option<&'static str> {
+    match opcode {
+        ArithmeticOpcode::Eq => Some("JEQ"),
+        ArithmeticOpcode::Gt => Some("JGT"),
+        ArithmeticOpcode::Lt => Some("JLT"),
+        _ => None,
+    }
+}
+
+/// Whether the entry function (usually Sys.init) is defined.
+pub fn defines_entry(commands: &[VmCommand], options: &Options) -> bool {
+    let entry = options.entry_function();
+    commands
+        .iter()
+        .any(|command| matches!(command, VmCommand::Label(label) if label == entry))
+}
+
+/// The bootstrap code is only generated when there is an entry function for it to call, and it
+/// hasn't been turned off.
+pub fn has_bootstrap(commands: &[VmCommand], options: &Options) -> bool {
+    options.bootstrap && defines_entry(commands, options)
+}
+
+pub fn translate(program: VmProgram, options: &Options) -> Result<String, Box<dyn Error>> {
+    let mut result = String::new();
+    translate_with_buffer(program, options, &mut result, None)?;
+    Ok(result)
+}
+
+/// Translates commands that aren't part of a `VmProgram`. Static variables are not split up by
+/// file here, so `static i` has to already be the index in the whole static segment, the same as
+/// it would be after parsing. Since the commands didn't come from a file, errors don't say where
+/// they happened. This wraps the commands in a program rather than `translate` calling this, since
+/// a program also has the files and positions that errors, `--file-index` and the other options
+/// need, which a bare list of commands doesn't.
+pub fn translate_commands(
+    commands: Vec<VmCommand>,
+    options: &Options,
+) -> Result<String, Box<dyn Error>> {
+    let mut program = VmProgram::new();
+    program.append_commands(commands);
+    translate(program, options)
+}
+
+/// Like `translate`, but calls `callback` with each command of the program and the range of bytes
+/// in the output its code takes up, in order, for building things like coverage tools on top of
+/// the translator. This happens once translation has succeeded, so nothing is called for programs
+/// with errors. The ranges don't overlap, though code the translator adds on its own like the
+/// bootstrap isn't part of any of them. A comparison fused with the if-goto after it gets all of
+/// the code for both, leaving an empty range for the if-goto. Dialects other than the standard one
+/// reformat the output afterwards, which the ranges don't account for.
+pub fn translate_with_callback(
+    program: VmProgram,
+    options: &Options,
+    callback: &mut Callback<'_>,
+) -> Result<String, Box<dyn Error>> {
+    let mut result = String::new();
+    translate_with_buffer(program, options, &mut result, Some(callback))?;
+    Ok(result)
+}
+
+/// Like `translate`, but writes the assembly into `buf` in place of whatever it held before. The
+/// memory the buffer already has is used for the output, so callers translating many programs
+/// can keep reusing the same one. If translation fails the buffer is left empty, but keeps its
+/// memory.
+pub fn translate_into(
+    program: VmProgram,
+    options: &Options,
+    buf: &mut Vec<u8>,
+) -> Result<(), Box<dyn Error>> {
+    let mut buffer = std::mem::take(buf);
+    buffer.clear();
+    let mut buffer = String::from_utf8(buffer).unwrap_or_default();
+    let outcome = translate_with_buffer(program, options, &mut buffer, None);
+    *buf = buffer.into_bytes();
+    outcome
+}
+
+/// Translates a program, building the output in `buffer`, which must be empty. If translation
+/// fails the buffer is left empty.
+fn translate_with_buffer<'a>(
+    program: VmProgram,
+    options: &'a Options,
+    buffer: &mut String,
+    callback: Option<&'a mut Callback<'a>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut translator = Translator::new(options);
+    translator.result = std::mem::take(buffer);
+    translator.callback = callback;
+    let outcome = translator.translate(program);
+    let mut result = std::mem::take(&mut translator.result);
+    let outcome = outcome.and_then(|()| {
+        if options.verify || cfg!(debug_assertions) {
+            check_internal_labels(&result)?;
+        }
+        Ok(())
+    });
+    if let Err(err) = outcome {
+        result.clear();
+        *buffer = result;
+        return Err(err);
     }
+    *buffer = apply_dialect(result, options.assembly_dialect);
+    Ok(())
+}
+
+/// Makes sure every label the translator made up for itself is actually defined somewhere, to
+/// catch bugs in the translator.
+fn check_internal_labels(result: &str) -> Result<(), Box<dyn Error>> {
+    let mut defined = HashSet::new();
+    let mut referenced = Vec::new();
+    for line in result.lines() {
+        let code = strip_comment(line);
+        if let Some(label) = code
+            .strip_prefix('(')
+            .and_then(|code| code.strip_suffix(')'))
+        {
+            defined.insert(label);
+        } else if let Some(symbol) = code.strip_prefix('@') {
+            if symbol.starts_with(INTERNAL_LABEL_PREFIX) {
+                referenced.push(symbol);
+            }
+        }
+    }
+    match referenced
+        .into_iter()
+        .find(|label| !defined.contains(label))
+    {
+        Some(label) => Err(CompileError::new(format!(
+            "Internal error: the translator used the label \"{}\" without defining it.",
+            label
+        )))?,
+        None => Ok(()),
+    }
+}
+
+/// Reformats finished assembly to suit a particular assembler or emulator.
+fn apply_dialect(result: String, dialect: AssemblyDialect) -> String {
+    if dialect == AssemblyDialect::Standard {
+        return result;
+    }
+    let mut formatted = String::with_capacity(result.len());
+    for line in result.lines() {
+        let comment = line
+            .find("//")
+            .map_or("", |comment_start| &line[comment_start..]);
+        let code = strip_comment(line);
+        let code = match (dialect, code) {
+            (AssemblyDialect::Nand2TetrisWeb, "0;JEQ") => "0;JMP",
+            _ => code,
+        };
+        if !code.is_empty() {
+            formatted.push_str(code);
+            formatted.push('\n');
+        } else if dialect == AssemblyDialect::Nand2TetrisWeb && !comment.is_empty() {
+            formatted.push_str(comment);
+            formatted.push('\n');
+        } else if dialect == AssemblyDialect::Nand2TetrisWeb && line.is_empty() {
+            formatted.push('\n');
+        }
+    }
+    formatted
+}
+
+/// Returns the assembly a single command turns into on its own, using the default options. Each
+/// call uses a fresh translator, so the ids of any labels it generates start over from 0.
+pub fn expand_command(command: &VmCommand) -> Result<String, Box<dyn Error>> {
+    let options = Options::default();
+    let mut translator = Translator::new(&options);
+    translator.translate_command(command.clone())?;
+    Ok(translator.result)
 }
 
-pub fn translate(program: VmProgram) -> Result<String, Box<dyn Error>> {
-    let translator = Translator {
-        next_unnamed_label_id: 0,
-        result: String::new(),
-        current_num_locals: 0,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assemble, parse,
+        test_util::{parse_program, translate_source, Computer},
     };
-    Ok(translator.translate(program.commands))
+
+    #[test]
+    fn comparisons_share_one_subroutine() {
+        let options = Options {
+            shared_comparisons: true,
+            ..Options::default()
+        };
+        let source = "push constant 3\npush constant 5\nlt\n\
+            push constant 5\npush constant 5\neq\n\
+            push constant 3\npush constant 5\ngt\n";
+        let result = translate_source(source, &options);
+        assert_eq!(result.matches(&format!("({})\n", COMPARE_LABEL)).count(), 1);
+        assert_eq!(
+            result
+                .matches(&format!("@{}\n0;JEQ", COMPARE_LABEL))
+                .count(),
+            3
+        );
+        for jump in &["D;JEQ", "D;JGT", "D;JLT"] {
+            assert_eq!(result.matches(jump).count(), 1);
+        }
+        let own_labels = result
+            .lines()
+            .filter(|line| line.starts_with('(') && !line.contains(COMPARE_LABEL));
+        assert_eq!(own_labels.count(), 0);
+        let mut computer = Computer::new(&result);
+        computer.ram[0] = 256;
+        computer.run(1000);
+        assert_eq!(computer.ram[0], 259);
+        assert_eq!(&computer.ram[256..259], &[0xFFFF, 0xFFFF, 0]);
+    }
+
+    #[test]
+    fn nop_is_only_a_comment() {
+        let program = parse_program("nop\n", &Options::default());
+        assert_eq!(program.commands, vec![VmCommand::Nop]);
+        let result = translate_source("nop\n", &Options::default());
+        assert!(result.contains("nop"));
+        assert!(result
+            .lines()
+            .all(|line| line.is_empty() || line.starts_with("//")));
+    }
+
+    #[test]
+    fn trace_ids_count_up_from_zero() {
+        let options = Options {
+            trace_comments: true,
+            ..Options::default()
+        };
+        let source = "push constant 1\npush constant 2\nadd\nlabel L\nif-goto L\n";
+        let result = translate_source(source, &options);
+        let ids: Vec<usize> = result
+            .lines()
+            .filter_map(|line| line.strip_prefix("// TRACE "))
+            .map(|id| id.parse().unwrap())
+            .collect();
+        assert_eq!(ids, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn quoted_names_are_mangled_into_labels() {
+        let source = "function \"Foo Bar\" 0\npush constant 0\nreturn\ncall \"Foo Bar\" 0\n";
+        let program = parse_program(source, &Options::default());
+        assert_eq!(
+            program.commands[0],
+            VmCommand::Label("Foo$20$Bar".to_owned())
+        );
+        let result = translate_source(source, &Options::default());
+        assert!(result.contains("// label \"Foo Bar\"\n(Foo$20$Bar)\n"));
+        assert!(result.contains("@Foo$20$Bar\n"));
+    }
+
+    #[test]
+    fn bootstrap_calls_the_entry_function() {
+        let options = Options {
+            entry: Some("Main.start".to_owned()),
+            ..Options::default()
+        };
+        let source = "function Main.start 0\npush constant 0\nreturn\n";
+        let result = translate_source(source, &options);
+        assert!(result.starts_with("// Bootstrap\n@256\n"));
+        assert!(result.contains("@Main.start\n0;JEQ\n"));
+        let without_entry = translate_source(source, &Options::default());
+        assert!(!without_entry.contains("Bootstrap"));
+    }
+
+    #[test]
+    fn many_locals_are_pushed_in_a_loop() {
+        let result = translate_source("function Foo 10\n", &Options::default());
+        assert!(result.contains("// push 10 locals in a loop\n@10\n"));
+        assert!(result.contains("MD=M-1   // decrement the counter\n"));
+        assert_eq!(result.matches("M=0").count(), 1);
+        let mut computer = Computer::new(&result);
+        computer.ram[0] = 256;
+        for address in 256..270 {
+            computer.ram[address] = 1234;
+        }
+        computer.run(1000);
+        assert_eq!(computer.ram[0], 266);
+        assert!(computer.ram[256..266].iter().all(|value| *value == 0));
+        assert_eq!(computer.ram[266], 1234);
+    }
+
+    #[test]
+    fn expands_a_single_command() {
+        let add = expand_command(&VmCommand::Arithmetic(ArithmeticOpcode::Add)).unwrap();
+        assert!(add.starts_with("// command: arithmetic\n"));
+        assert!(
+            add.contains("@SP      // Load spa into A\nA=M-1    // Load *spa-1 into A\nM=M+D\n")
+        );
+        let eq = VmCommand::Arithmetic(ArithmeticOpcode::Eq);
+        let first = expand_command(&eq).unwrap();
+        assert!(first.contains("(__VM_IMPL_LABEL_0)"));
+        assert_eq!(expand_command(&eq).unwrap(), first);
+    }
+
+    #[test]
+    fn annotations_go_above_their_command() {
+        let source = "push constant 1\n// @src Foo.jack:42\npush constant 2\n// @later\nadd\n";
+        let result = translate_source(source, &Options::default());
+        assert_eq!(result.matches("// @").count(), 1);
+        assert!(result.contains("\n// @src Foo.jack:42\n// command: push Constant 2\n"));
+    }
+
+    #[test]
+    fn returns_know_the_locals_of_their_own_function() {
+        let source = "function Foo 2\npush constant 0\nreturn\n\
+            function Bar 0\npush constant 0\nreturn\n";
+        let result = translate_source(source, &Options::default());
+        let returns: Vec<_> = result
+            .lines()
+            .filter(|line| line.starts_with("// command: return"))
+            .collect();
+        assert_eq!(
+            returns,
+            vec![
+                "// command: return (2 locals)",
+                "// command: return (0 locals)"
+            ]
+        );
+    }
+
+    /// Runs a program without any functions, returning the stack.
+    fn run_stack(source: &str, options: &Options) -> Vec<u16> {
+        let mut computer = Computer::new(&translate_source(source, options));
+        computer.ram[0] = 256;
+        computer.run(10_000);
+        computer.ram[256..computer.ram[0] as usize].to_vec()
+    }
+
+    #[test]
+    fn neg_wraps_in_twos_complement() {
+        let options = Options::default();
+        assert_eq!(
+            run_stack("push constant 32767\nneg\n", &options),
+            vec![0x8001]
+        );
+        assert_eq!(
+            run_stack("push constant 32767\nneg\nneg\n", &options),
+            vec![32767]
+        );
+        // -32768 is its own negation.
+        let source = "push constant 32767\nneg\npush constant 1\nsub\nneg\n";
+        assert_eq!(run_stack(source, &options), vec![0x8000]);
+        assert_eq!(run_stack("push constant 0\nneg\n", &options), vec![0]);
+    }
+
+    #[test]
+    fn untranslatable_commands_are_errors() {
+        // The parser rejects this, so the commands are put together by hand.
+        let commands = vec![
+            VmCommand::IfGoto("L".to_owned()),
+            VmCommand::Pop(MemorySegment::Constant, 3),
+        ];
+        let err = translate_commands(commands, &Options::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Cannot translate \"pop Constant 3\", there is nowhere to pop the value into."
+        );
+    }
+
+    #[test]
+    fn dialects_reformat_the_same_command() {
+        let with = |assembly_dialect| Options {
+            assembly_dialect,
+            ..Options::default()
+        };
+        let source = "label LOOP\ngoto LOOP\n";
+        let standard = translate_source(source, &with(AssemblyDialect::Standard));
+        assert!(standard.contains("(LOOP)\n@LOOP\n0;JEQ\n"));
+        let web = translate_source(source, &with(AssemblyDialect::Nand2TetrisWeb));
+        assert!(web.contains("(LOOP)\n@LOOP\n0;JMP\n"));
+        assert!(web
+            .lines()
+            .all(|line| !line.contains("//") || line.starts_with("//")));
+        let strict = translate_source(source, &with(AssemblyDialect::Strict));
+        assert_eq!(strict, "(LOOP)\n@LOOP\n0;JEQ\n");
+    }
+
+    #[test]
+    fn calls_without_arguments_point_arg_at_the_frame() {
+        let source = "push constant 11\ncall Foo 0\nlabel END\ngoto END\n\
+            function Foo 0\npush argument 0\npop temp 0\npush constant 42\nreturn\n";
+        let result = translate_source(source, &Options::default());
+        let mut computer = Computer::new(&result);
+        computer.ram[0] = 256;
+        let foo = Computer::label_address(&result, "Foo");
+        let end = Computer::label_address(&result, "END");
+        computer.run_until(foo, 1000);
+        // The frame starts right where the arguments would be: the return address, then the
+        // caller's LCL, ARG, THIS, and THAT.
+        assert_eq!(computer.ram[2], 257);
+        assert_eq!(computer.ram[257] as usize, end);
+        assert_eq!(computer.ram[1], 262);
+        assert_eq!(computer.ram[0], 262);
+        computer.run_until(end, 1000);
+        // With nothing to read, argument 0 is the first slot of the frame.
+        assert_eq!(computer.ram[5] as usize, end);
+        // The return value replaces the missing arguments and the frame.
+        assert_eq!(computer.ram[0], 258);
+        assert_eq!(&computer.ram[256..258], &[11, 42]);
+    }
+
+    #[test]
+    fn internal_labels_have_to_be_defined() {
+        let result = translate_source(
+            "push constant 1\npush constant 2\neq\n",
+            &Options::default(),
+        );
+        assert!(check_internal_labels(&result).is_ok());
+        let broken = result.replace("(__VM_IMPL_LABEL_0)", "");
+        let err = check_internal_labels(&broken).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Internal error: the translator used the label \"__VM_IMPL_LABEL_0\" without \
+            defining it."
+        );
+    }
+
+    #[test]
+    fn indirect_segments_use_their_own_pointer() {
+        use MemorySegment::*;
+        let segments = [
+            (Local, "@LCL"),
+            (Argument, "@ARG"),
+            (This, "@THIS"),
+            (That, "@THAT"),
+        ];
+        for (segment, pointer) in &segments {
+            for command in &[VmCommand::Push(*segment, 2), VmCommand::Pop(*segment, 2)] {
+                let code = expand_command(command).unwrap();
+                let pointers: Vec<_> = ["@LCL", "@ARG", "@THIS", "@THAT"]
+                    .iter()
+                    .filter(|symbol| code.contains(&format!("{}\n", symbol)))
+                    .collect();
+                assert_eq!(pointers, vec![pointer], "{}", code);
+            }
+        }
+    }
+
+    #[test]
+    fn raw_assembly_is_copied_as_it_is() {
+        let options = Options {
+            allow_unknown_command: true,
+            ..Options::default()
+        };
+        let result = translate_source("push constant 1\n!   @SP\n! AM=M-1\n", &options);
+        assert!(
+            result.contains("// command: raw assembly\n@SP\n// command: raw assembly\nAM=M-1\n")
+        );
+        let mut program = VmProgram::new();
+        let err = parse::parse(&mut program, "!\n", "Test.vm", &options).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Expected assembly code after \"!\"."));
+        let err = parse::parse(&mut program, "! @SP\n", "Test.vm", &Options::default());
+        assert!(err
+            .unwrap_err()
+            .to_string()
+            .contains("--allow-unknown-command"));
+    }
+
+    #[test]
+    fn locals_are_named_in_comments() {
+        let source = "// @locals sum i\nfunction Foo 2\npush constant 0\nreturn\n\
+            // @locals x\nfunction Bar 2\npush constant 0\nreturn\n";
+        let result = translate_source(source, &Options::default());
+        assert!(result.contains("// command: function 2\n// local 0 = sum\n// local 1 = i\n"));
+        // The names for Bar don't match how many locals it has.
+        assert_eq!(result.matches("// local ").count(), 2);
+    }
+
+    #[test]
+    fn many_comparisons_add_no_labels() {
+        let options = Options {
+            shared_comparisons: true,
+            ..Options::default()
+        };
+        let source: String = (0..20)
+            .map(|index| format!("push constant {}\npush constant 10\nlt\n", index))
+            .collect();
+        let result = translate_source(&source, &options);
+        let labels: Vec<_> = result
+            .lines()
+            .filter(|line| line.starts_with('('))
+            .collect();
+        let subroutine = result.matches(&format!("({})\n", COMPARE_LABEL)).count();
+        assert_eq!(subroutine, 1);
+        assert!(labels.iter().all(|label| label.contains(COMPARE_LABEL)));
+        // Fewer labels than comparisons, and the same number no matter how many there are.
+        let fewer = translate_source("push constant 1\npush constant 10\nlt\n", &options);
+        let fewer_labels = fewer.lines().filter(|line| line.starts_with('('));
+        assert_eq!(fewer_labels.count(), labels.len());
+        assert!(labels.len() < 20);
+        assert!(result.len() < translate_source(&source, &Options::default()).len());
+        let stack = run_stack(&source, &options);
+        let expected: Vec<u16> = (0..20)
+            .map(|index| if index < 10 { 0xFFFF } else { 0 })
+            .collect();
+        assert_eq!(stack, expected);
+    }
+
+    #[test]
+    fn higher_optimize_levels_give_fewer_instructions() {
+        let level = |level: &str| {
+            let args = [format!("-O{}", level), "Prog".to_owned()];
+            Options::from_args(args.iter().cloned()).unwrap()
+        };
+        let mut source = "push constant 2\npush constant 3\nadd\ngoto END\npush constant 9\n\
+            label END\n"
+            .to_owned();
+        for _ in 0..8 {
+            source.push_str("push constant 4\npush constant 7\nlt\npop temp 0\n");
+        }
+        let source = &source[..];
+        let naive = translate_source(source, &level("0"));
+        assert_eq!(naive, translate_source(source, &Options::default()));
+        let optimized = translate_source(source, &level("2"));
+        let naive_count = assemble::count_instructions(&naive);
+        let optimized_count = assemble::count_instructions(&optimized);
+        assert!(
+            optimized_count < naive_count,
+            "-O2 gave {} instructions and -O0 gave {}",
+            optimized_count,
+            naive_count
+        );
+        assert_eq!(
+            run_stack(source, &level("2")),
+            run_stack(source, &level("0"))
+        );
+    }
+
+    #[test]
+    fn debug_asserts_trap_on_stack_underflow() {
+        let source = "push constant 1\nadd\n";
+        assert!(!translate_source(source, &Options::default()).contains(TRAP_LABEL));
+        let options = Options {
+            debug_asserts: true,
+            ..Options::default()
+        };
+        let result = translate_source(source, &options);
+        assert!(result.contains("// assert: the stack has at least 2 values\n@SP\nD=M\n@258\n"));
+        assert!(result.contains("(__VM_TRAP)\n@__VM_TRAP\n0;JEQ\n"));
+        let trap = Computer::label_address(&result, TRAP_LABEL);
+        let mut computer = Computer::new(&result);
+        computer.ram[0] = 256;
+        computer.run_until(trap, 1000);
+        // Nothing ran after the check failed.
+        assert_eq!(computer.ram[0], 257);
+    }
+
+    #[test]
+    fn eight_bit_words_have_their_own_limits() {
+        let options = Options {
+            word_bits: 8,
+            ..Options::default()
+        };
+        assert_eq!((options.max_constant(), options.true_value()), (127, 255));
+        let err = parse::parse(
+            &mut VmProgram::new(),
+            "push constant 128\n",
+            "Test.vm",
+            &options,
+        );
+        assert!(err
+            .unwrap_err()
+            .to_string()
+            .contains("the integer \"128\" is too big (expected 127 or below)."));
+        // -1 is 255 with 8 bits, and 127 + 1 wraps around to -128.
+        let source = "push constant 3\npush constant 3\neq\npush constant 0\nnot\n\
+            push constant 1\nneg\npush constant 1\nlt\npush constant 127\npush constant 1\n\
+            add\npush constant 0\nlt\npush constant 100\npush constant 0\npush constant 100\n\
+            sub\ngt\n";
+        let expected = vec![255, 255, 255, 255, 255];
+        let folded = Options {
+            fold_constants: true,
+            ..options.clone()
+        };
+        let shared = Options {
+            shared_comparisons: true,
+            ..options.clone()
+        };
+        for options in &[options.clone(), folded, shared] {
+            assert_eq!(run_stack(source, options), expected);
+        }
+        let fused = Options {
+            fuse_branches: true,
+            ..options
+        };
+        let source = "push constant 0\npush constant 1\nsub\npush constant 1\nlt\nif-goto T\n\
+            push constant 5\ngoto END\nlabel T\npush constant 9\nlabel END\n";
+        assert_eq!(run_stack(source, &fused), vec![9]);
+    }
+
+    #[test]
+    fn deterministic_labels_survive_inserting_a_command() {
+        let before =
+            "push constant 1\npush constant 2\nadd\npush constant 3\npush constant 4\nlt\n";
+        let after = "push constant 1\npush constant 2\nadd push constant 0 eq not\n\
+            push constant 3\npush constant 4\nlt\n";
+        let last_label = |source: &str, options: &Options| {
+            let result = translate_source(source, options);
+            let mut labels = result.lines().filter(|line| line.starts_with("(__"));
+            labels.next_back().unwrap().to_owned()
+        };
+        let options = Options::default();
+        assert_ne!(last_label(before, &options), last_label(after, &options));
+        let options = Options {
+            deterministic_labels: true,
+            ..Options::default()
+        };
+        assert_eq!(last_label(before, &options), last_label(after, &options));
+        assert_eq!(last_label(after, &options), "(__VM_IMPL_LABEL_Test.vm$6)");
+        // A line with two comparisons needs two different labels.
+        let result = translate_source(
+            "push constant 1 push constant 2 eq push constant 3 eq\n",
+            &options,
+        );
+        assert!(result.contains("(__VM_IMPL_LABEL_Test.vm$1)\n"));
+        assert!(result.contains("(__VM_IMPL_LABEL_Test.vm$1$1)\n"));
+    }
+
+    #[test]
+    fn translating_into_a_buffer_gives_the_same_bytes() {
+        let options = Options::default();
+        let source = "function Main.main 1\npush constant 3\npush local 0\nlt\nreturn\n";
+        let expected = translate_source(source, &options);
+        let mut buf = b"left over from before".to_vec();
+        buf.reserve(expected.len() * 2);
+        let capacity = buf.capacity();
+        translate_into(parse_program(source, &options), &options, &mut buf).unwrap();
+        assert_eq!(buf, expected.as_bytes());
+        assert_eq!(buf.capacity(), capacity);
+
+        // A failed translation leaves nothing in the buffer, but still hands back its memory.
+        let mut program = VmProgram::new();
+        program.push_command(VmCommand::Pop(MemorySegment::Constant, 0));
+        assert!(translate_into(program, &options, &mut buf).is_err());
+        assert!(buf.is_empty());
+        assert_eq!(buf.capacity(), capacity);
+    }
+
+    #[test]
+    fn file_index_lists_where_each_file_starts() {
+        let options = Options {
+            file_index: true,
+            ..Options::default()
+        };
+        let mut program = VmProgram::new();
+        let main = "function Main.main 0\npush constant 1\nreturn\n";
+        parse::parse(&mut program, main, "Main.vm", &options).unwrap();
+        let other = "function Other.f 0\npush constant 2\npush constant 3\nadd\nreturn\n";
+        parse::parse(&mut program, other, "Other.vm", &options).unwrap();
+        let result = translate(program, &options).unwrap();
+        let lines: Vec<_> = result.lines().collect();
+        assert_eq!(lines[0], "// Files:");
+        assert_eq!(&lines[3], &"");
+        let mut previous_end = 3;
+        let files = [("Main.vm", "(Main.main)"), ("Other.vm", "(Other.f)")];
+        for (entry, (file, first_line)) in lines[1..3].iter().zip(&files) {
+            let prefix = format!("//     {}: lines ", file);
+            assert!(entry.starts_with(&prefix), "{}", entry);
+            let range: Vec<usize> = entry[prefix.len()..]
+                .split('-')
+                .map(|number| number.parse().unwrap())
+                .collect();
+            assert!(previous_end < range[0] && range[0] < range[1]);
+            assert!(range[1] <= lines.len());
+            assert_eq!(&lines[range[0] - 1], first_line);
+            previous_end = range[1];
+        }
+    }
+
+    #[test]
+    fn fuses_a_comparison_into_the_if_goto_after_it() {
+        let fused = Options {
+            fuse_branches: true,
+            ..Options::default()
+        };
+        let program = |a: i32, b: i32| {
+            format!(
+                "push constant {}\npush constant {}\nlt\nif-goto LESS\npush constant 5\n\
+                goto END\nlabel LESS\npush constant 9\nlabel END\n",
+                a, b
+            )
+        };
+        let result = translate_source(&program(1, 2), &fused);
+        assert!(result.contains("// command: if-goto LESS\n@LESS\nD;JLT\n"));
+        // No boolean is made for the if-goto to test.
+        assert!(!result.contains("D;JNE"));
+        assert!(translate_source(&program(1, 2), &Options::default()).contains("D;JNE"));
+        for (a, b) in &[(1, 2), (2, 1), (3, 3)] {
+            let expected = run_stack(&program(*a, *b), &Options::default());
+            assert_eq!(expected, vec![if a < b { 9 } else { 5 }]);
+            assert_eq!(run_stack(&program(*a, *b), &fused), expected);
+        }
+    }
+
+    #[test]
+    fn callback_sees_each_command_once() {
+        let options = Options::default();
+        let source = "function Main.f 1\npush constant 2\npop local 0\npush local 0\n\
+            push constant 3\nlt\nif-goto END\nlabel END\npush constant 0\nreturn\n";
+        let program = parse_program(source, &options);
+        let commands = program.commands.clone();
+        let mut seen = Vec::new();
+        let result = translate_with_callback(program, &options, &mut |command, range| {
+            seen.push((command.clone(), range))
+        })
+        .unwrap();
+        let seen_commands: Vec<_> = seen.iter().map(|(command, _)| command.clone()).collect();
+        assert_eq!(seen_commands, commands);
+        let mut end = 0;
+        for (command, range) in &seen {
+            assert!(end <= range.start && range.start <= range.end);
+            end = range.end;
+            if let VmCommand::Push(..) = command {
+                assert!(result[range.clone()].starts_with("// command: push"));
+            }
+        }
+        assert!(end <= result.len());
+    }
+
+    #[test]
+    fn translates_a_hand_built_vector() {
+        use ArithmeticOpcode::*;
+        let commands = vec![
+            VmCommand::Push(MemorySegment::Constant, 7),
+            VmCommand::Push(MemorySegment::Constant, 8),
+            VmCommand::Arithmetic(Add),
+            VmCommand::Pop(MemorySegment::Static, 3),
+            VmCommand::Push(MemorySegment::Static, 3),
+        ];
+        let options = Options {
+            trace_comments: true,
+            ..Options::default()
+        };
+        let result = translate_commands(commands.clone(), &options).unwrap();
+        assert_eq!(result.matches("// TRACE ").count(), commands.len());
+        let result = translate_commands(commands, &Options::default()).unwrap();
+        // The static index is used as it is, without a file to offset it.
+        assert!(result.contains("@19\n"));
+        let mut computer = Computer::new(&result);
+        computer.ram[0] = 256;
+        computer.run(1000);
+        assert_eq!(computer.ram[19], 15);
+        assert_eq!(&computer.ram[256..computer.ram[0] as usize], &[15]);
+    }
+
+    #[test]
+    fn separators_go_before_functions_and_count_for_their_file() {
+        let options = Options {
+            section_per_function: true,
+            file_index: true,
+            ..Options::default()
+        };
+        let mut program = VmProgram::new();
+        let main = "function Main.main 0\npush constant 1\nreturn\n";
+        parse::parse(&mut program, main, "Main.vm", &options).unwrap();
+        let other = "function Other.f 0\npush constant 2\nreturn\n";
+        parse::parse(&mut program, other, "Other.vm", &options).unwrap();
+        let result = translate(program, &options).unwrap();
+        let separator = format!("// {}", "=".repeat(SECTION_SEPARATOR_WIDTH));
+        let lines: Vec<_> = result.lines().collect();
+        for (entry, name) in lines[1..3].iter().zip(&["Main.main", "Other.f"]) {
+            let first_line: usize = entry
+                .split("lines ")
+                .nth(1)
+                .and_then(|range| range.split('-').next())
+                .unwrap()
+                .parse()
+                .unwrap();
+            let function_line = format!("// function {}", name);
+            let expected = [
+                "",
+                separator.as_str(),
+                function_line.as_str(),
+                separator.as_str(),
+            ];
+            assert_eq!(&lines[first_line - 1..first_line + 3], &expected);
+            assert_eq!(lines[first_line + 3], format!("({})", name));
+        }
+    }
+
+    #[test]
+    fn fixed_segments_take_the_shortest_path() {
+        let code = |command: VmCommand| -> Vec<String> {
+            let code = expand_command(&command).unwrap();
+            let code = code.lines().map(strip_comment);
+            code.filter(|line| !line.is_empty())
+                .map(str::to_owned)
+                .collect()
+        };
+        assert_eq!(
+            code(VmCommand::Push(MemorySegment::Temp, 2)),
+            vec!["@7", "D=M", "@SP", "M=M+1", "A=M-1", "M=D"]
+        );
+        assert_eq!(
+            code(VmCommand::Push(MemorySegment::Pointer, 1)),
+            vec!["@4", "D=M", "@SP", "M=M+1", "A=M-1", "M=D"]
+        );
+        assert_eq!(
+            code(VmCommand::Pop(MemorySegment::Temp, 7)),
+            vec!["@SP", "AM=M-1", "D=M", "@12", "M=D"]
+        );
+        assert_eq!(
+            code(VmCommand::Pop(MemorySegment::Pointer, 0)),
+            vec!["@SP", "AM=M-1", "D=M", "@3", "M=D"]
+        );
+        let source = "push constant 5\npop temp 3\npush constant 6\npop pointer 1\n\
+            push pointer 1\npush temp 3\n";
+        assert_eq!(run_stack(source, &Options::default()), vec![6, 5]);
+    }
 }
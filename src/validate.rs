@@ -0,0 +1,679 @@
+use crate::{
+    error::{CompileError, Location, Severity},
+    layout::MEMORY_MAPPED_IO_START,
+    options::Options,
+    stack_depth, translate,
+    vm_program::{MemorySegment, VmCommand, VmProgram},
+};
+use std::{collections::HashMap, error::Error};
+
+/// Where the command at the given index came from, with columns reported the way the user asked.
+fn location(program: &VmProgram, index: usize, options: &Options) -> Option<Location> {
+    let mut location = program.location(index)?;
+    location.col = location.col - 1 + options.column_base;
+    Some(location)
+}
+
+fn error_at(program: &VmProgram, index: usize, options: &Options, message: String) -> CompileError {
+    match location(program, index, options) {
+        Some(location) => CompileError::at(message, location),
+        None => CompileError::new(message),
+    }
+}
+
+/// Describes where a command came from, for mentioning other commands in an error message.
+fn describe_location(program: &VmProgram, index: usize, options: &Options) -> String {
+    match location(program, index, options) {
+        Some(location) => format!("at {}:{}:{}", location.file, location.line, location.col),
+        None => "elsewhere".to_owned(),
+    }
+}
+
+/// Looks for problems that would make the translated program incorrect.
+pub fn validate(program: &VmProgram, options: &Options) -> Result<(), Box<dyn Error>> {
+    check_duplicate_definitions(program, options)?;
+    check_entry_defined(program, options)?;
+    check_returns_in_functions(program, options)?;
+    Ok(())
+}
+
+/// Looks for code that will translate fine but probably won't do what the author wanted.
+pub fn lint(program: &VmProgram, options: &Options) -> Vec<CompileError> {
+    let mut warnings = Vec::new();
+    check_uninitialized_function(program, options, &mut warnings);
+    check_empty_loops(program, options, &mut warnings);
+    check_unset_pointers(program, options, &mut warnings);
+    check_pointer_constants(program, options, &mut warnings);
+    check_empty_returns(program, options, &mut warnings);
+    check_call_arities(program, options, &mut warnings);
+    if options.warn_unused_values {
+        check_unused_values(program, options, &mut warnings);
+    }
+    warnings
+}
+
+/// Removes labels that are immediately defined again with the same name, which can happen when
+/// generated code is pasted together. Since nothing can happen between the two definitions they
+/// mean the same thing, so only a warning is given for each one removed.
+pub fn merge_adjacent_labels(program: &mut VmProgram, options: &Options) -> Vec<CompileError> {
+    let mut warnings = Vec::new();
+    let mut index = 1;
+    while index < program.commands.len() {
+        let duplicate = match (&program.commands[index - 1], &program.commands[index]) {
+            (VmCommand::Label(previous), VmCommand::Label(name)) if previous == name => {
+                Some(name.clone())
+            }
+            _ => None,
+        };
+        let is_function = matches!(
+            program.commands.get(index + 1),
+            Some(VmCommand::FnSetup { .. })
+        );
+        if let (Some(name), false) = (duplicate, is_function) {
+            let message = format!(
+                "The label \"{}\" is defined twice in a row, so the second definition was removed.",
+                name
+            );
+            let mut warning = error_at(program, index, options, message);
+            warning.severity = Severity::Warning;
+            warnings.push(warning);
+            program.commands.remove(index);
+            program.info.remove(index);
+        } else {
+            index += 1;
+        }
+    }
+    warnings
+}
+
+/// Both labels and functions turn into labels in the output, so each name can only be defined
+/// once across both of them. With `Options::case_insensitive`, names that only differ by case
+/// count as the same name.
+fn check_duplicate_definitions(
+    program: &VmProgram,
+    options: &Options,
+) -> Result<(), Box<dyn Error>> {
+    let mut definitions: HashMap<String, (usize, &str, &str)> = HashMap::new();
+    for (index, command) in program.commands.iter().enumerate() {
+        let name = match command {
+            VmCommand::Label(name) => name,
+            _ => continue,
+        };
+        // Functions are a label immediately followed by the setup for their locals.
+        let kind = match program.commands.get(index + 1) {
+            Some(VmCommand::FnSetup { .. }) => "function",
+            _ => "label",
+        };
+        let key = if options.case_insensitive {
+            name.to_lowercase()
+        } else {
+            name.clone()
+        };
+        if let Some((previous_index, previous_kind, previous_name)) =
+            definitions.insert(key, (index, kind, name))
+        {
+            let what = if kind == previous_kind {
+                format!("as a {} twice", kind)
+            } else {
+                "as both a label and a function".to_owned()
+            };
+            let name = if previous_name == name {
+                format!("The name \"{}\"", name)
+            } else {
+                format!(
+                    "Ignoring case, the name \"{}\" (also written \"{}\")",
+                    name, previous_name
+                )
+            };
+            let message = format!(
+                "{} is defined {} (previously defined {}).",
+                name,
+                what,
+                describe_location(program, previous_index, options)
+            );
+            Err(error_at(program, index, options, message))?;
+        }
+    }
+    Ok(())
+}
+
+/// A `return` restores the frame of the function it is in, so one outside of any function would
+/// restore a frame that was never made, from whatever garbage LCL points at. Code at the top of a
+/// file doesn't belong to a function from an earlier file, the same as when translating, but an
+/// included file is part of whatever function includes it.
+fn check_returns_in_functions(
+    program: &VmProgram,
+    options: &Options,
+) -> Result<(), Box<dyn Error>> {
+    let file = |index: usize| {
+        let info = &program.info[index];
+        let file = info.position.as_ref().map(|pos| pos.file);
+        info.included_in.or(file)
+    };
+    let mut in_function = false;
+    for (index, command) in program.commands.iter().enumerate() {
+        if index > 0 && file(index) != file(index - 1) {
+            in_function = false;
+        }
+        match command {
+            VmCommand::FnSetup { .. } => in_function = true,
+            VmCommand::Return if !in_function => {
+                let message = "This \"return\" is not inside of any function, so there is no \
+                    frame for it to return from. Add a \"function\" before it, or use \"goto\" to \
+                    stop the program instead."
+                    .to_owned();
+                Err(error_at(program, index, options, message))?;
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Makes calls and jumps use the same capitalization as the label or function they refer to, for
+/// `Options::case_insensitive`. This has to happen after validation, which makes sure no two
+/// names only differ by case.
+pub fn resolve_names_ignoring_case(program: &mut VmProgram) {
+    let definitions: HashMap<String, String> = program
+        .commands
+        .iter()
+        .filter_map(|command| match command {
+            VmCommand::Label(name) => Some((name.to_lowercase(), name.clone())),
+            _ => None,
+        })
+        .collect();
+    for command in &mut program.commands {
+        let target = match command {
+            VmCommand::Call { fn_name: name, .. }
+            | VmCommand::Goto(name)
+            | VmCommand::IfGoto(name) => name,
+            _ => continue,
+        };
+        if let Some(defined) = definitions.get(&target.to_lowercase()) {
+            target.clone_from(defined);
+        }
+    }
+}
+
+/// Asking for a specific entry function only makes sense if it exists. Without bootstrap code
+/// nothing calls it, so then it doesn't matter.
+fn check_entry_defined(program: &VmProgram, options: &Options) -> Result<(), Box<dyn Error>> {
+    let required = options.bootstrap && (options.entry.is_some() || options.require_entry);
+    if required && !translate::defines_entry(&program.commands, options) {
+        Err(CompileError::new(format!(
+            "The entry function \"{}\" is not defined anywhere.",
+            options.entry_function()
+        )))?;
+    }
+    Ok(())
+}
+
+/// Without the bootstrap code or a caller, nothing sets up SP and LCL before a function pushes
+/// its locals. This is normal for a lone Chapter 7 style file, but not for one with functions.
+fn check_uninitialized_function(
+    program: &VmProgram,
+    options: &Options,
+    warnings: &mut Vec<CompileError>,
+) {
+    if translate::has_bootstrap(&program.commands, options) {
+        return;
+    }
+    let mut previous: Option<&VmCommand> = None;
+    for (index, command) in program.commands.iter().enumerate() {
+        match command {
+            VmCommand::Call { .. } => return,
+            VmCommand::FnSetup { .. } => {
+                let name = match previous {
+                    Some(VmCommand::Label(name)) => &name[..],
+                    _ => "?",
+                };
+                let message = format!(
+                    "The function \"{}\" runs before any call or bootstrap code, so the stack \
+                    may not be set up for its locals.",
+                    name
+                );
+                let mut warning = error_at(program, index, options, message);
+                warning.severity = Severity::Warning;
+                warnings.push(warning);
+                return;
+            }
+            _ => (),
+        }
+        previous = Some(command);
+    }
+}
+
+/// `label L` followed by `goto L` loops forever without doing anything. That is the usual way to
+/// halt at the end of a program or function, but anywhere else it is probably a missing body.
+fn check_empty_loops(program: &VmProgram, options: &Options, warnings: &mut Vec<CompileError>) {
+    for index in 1..program.commands.len() {
+        let name = match (&program.commands[index - 1], &program.commands[index]) {
+            (VmCommand::Label(label), VmCommand::Goto(target)) if label == target => target,
+            _ => continue,
+        };
+        let file = |index: usize| program.info[index].position.as_ref().map(|pos| pos.file);
+        let is_halt = match program.commands.get(index + 1) {
+            None => true,
+            Some(_) if file(index + 1) != file(index) => true,
+            Some(VmCommand::Label(_)) => matches!(
+                program.commands.get(index + 2),
+                Some(VmCommand::FnSetup { .. })
+            ),
+            Some(_) => false,
+        };
+        if !is_halt {
+            let message = format!(
+                "\"goto {}\" jumps back to the label right before it, making an infinite loop with \
+                an empty body.",
+                name
+            );
+            let mut warning = error_at(program, index, options, message);
+            warning.severity = Severity::Warning;
+            warnings.push(warning);
+        }
+    }
+}
+
+/// A function gets its caller's THIS and THAT, which usually have nothing to do with it, so using
+/// `this` or `that` in a function that never pops into the matching part of `pointer` is probably
+/// a mistake. This doesn't follow where the code goes, so it only catches functions that never set
+/// the pointer at all. Raw assembly might set it, and commands before the first function are
+/// skipped since test scripts often set THIS and THAT themselves.
+fn check_unset_pointers(program: &VmProgram, options: &Options, warnings: &mut Vec<CompileError>) {
+    use MemorySegment::*;
+    for function in program.functions() {
+        let is_raw_asm = |command: &VmCommand| matches!(command, VmCommand::RawAsm(_));
+        if function.name.is_none() || function.body.iter().any(is_raw_asm) {
+            continue;
+        }
+        for (segment, pointer_index) in [(This, 0), (That, 1)] {
+            if function
+                .body
+                .contains(&VmCommand::Pop(Pointer, pointer_index))
+            {
+                continue;
+            }
+            let first_use = function.body.iter().position(|command| match command {
+                VmCommand::Push(used, _) | VmCommand::Pop(used, _) => *used == segment,
+                _ => false,
+            });
+            if let Some(index) = first_use {
+                let message = format!(
+                    "The function \"{}\" uses \"{}\" without ever setting it with \
+                    \"pop pointer {}\", so it will access whatever its caller left there.",
+                    function.name.as_deref().unwrap_or("?"),
+                    segment.name().unwrap_or("?"),
+                    pointer_index
+                );
+                let mut warning = error_at(program, function.start + index, options, message);
+                warning.severity = Severity::Warning;
+                warnings.push(warning);
+            }
+        }
+    }
+}
+
+/// Jack programs only point THIS and THAT at objects and arrays on the heap, so pointing one past
+/// the end of it with a constant is probably a typo.
+fn check_pointer_constants(
+    program: &VmProgram,
+    options: &Options,
+    warnings: &mut Vec<CompileError>,
+) {
+    use MemorySegment::*;
+    for index in 1..program.commands.len() {
+        let (value, pointer_index) = match (&program.commands[index - 1], &program.commands[index])
+        {
+            (VmCommand::Push(Constant, value), VmCommand::Pop(Pointer, pointer_index)) => {
+                (*value, *pointer_index)
+            }
+            _ => continue,
+        };
+        if value >= MEMORY_MAPPED_IO_START {
+            let message = format!(
+                "\"pop pointer {}\" sets {} to {}, which is past the end of the heap at {}.",
+                pointer_index,
+                if pointer_index == 0 { "THIS" } else { "THAT" },
+                value,
+                MEMORY_MAPPED_IO_START - 1
+            );
+            let mut warning = error_at(program, index, options, message);
+            warning.severity = Severity::Warning;
+            warnings.push(warning);
+        }
+    }
+}
+
+/// Every function returns a value, even void ones, so a `return` with nothing pushed since the
+/// function started returns whatever happens to be above the locals.
+fn check_empty_returns(program: &VmProgram, options: &Options, warnings: &mut Vec<CompileError>) {
+    for function in program.functions() {
+        if function.name.is_none() {
+            continue;
+        }
+        let depths = match stack_depth::terminator_depths(&function) {
+            Some(depths) => depths,
+            None => continue,
+        };
+        for (index, depth) in depths {
+            let empty = depth <= function.num_locals as isize;
+            if empty && function.body[index] == VmCommand::Return {
+                let message = "There is no value on the stack for this \"return\" to return, add \
+                    \"push constant 0\" before it if the function doesn't return anything."
+                    .to_owned();
+                let mut warning = error_at(program, function.start + index, options, message);
+                warning.severity = Severity::Warning;
+                warnings.push(warning);
+            }
+        }
+    }
+}
+
+/// Functions don't say how many arguments they take, but a function always takes the same number,
+/// so calls to one function that pass different numbers of arguments can't all be right. Each call
+/// is compared with the first call to the same function.
+fn check_call_arities(program: &VmProgram, options: &Options, warnings: &mut Vec<CompileError>) {
+    let mut first_calls: HashMap<&str, (usize, usize)> = HashMap::new();
+    for (index, command) in program.commands.iter().enumerate() {
+        let (fn_name, num_args) = match command {
+            VmCommand::Call { fn_name, num_args } => (fn_name, *num_args),
+            _ => continue,
+        };
+        let (first_index, first_num_args) =
+            *first_calls.entry(fn_name).or_insert((index, num_args));
+        if num_args != first_num_args {
+            let arguments = if num_args == 1 {
+                "argument"
+            } else {
+                "arguments"
+            };
+            let message = format!(
+                "This calls \"{}\" with {} {}, but the call {} passes {}.",
+                fn_name,
+                num_args,
+                arguments,
+                describe_location(program, first_index, options),
+                first_num_args
+            );
+            let mut warning = error_at(program, index, options, message);
+            warning.severity = Severity::Warning;
+            warnings.push(warning);
+        }
+    }
+}
+
+/// Code generated for a well-formed expression consumes everything it pushes, so only a function's
+/// locals should be on the stack at a `goto`, plus the return value at a `return`. Anything more is
+/// probably a value that was pushed and then forgotten about, like a call to a void function
+/// whose result wasn't popped. Commands before the first function are skipped, since test
+/// programs often leave their results on the stack on purpose.
+fn check_unused_values(program: &VmProgram, options: &Options, warnings: &mut Vec<CompileError>) {
+    for function in program.functions() {
+        if function.name.is_none() {
+            continue;
+        }
+        let depths = match stack_depth::terminator_depths(&function) {
+            Some(depths) => depths,
+            None => continue,
+        };
+        for (index, depth) in depths {
+            let (command, expected) = match &function.body[index] {
+                VmCommand::Return => ("return", function.num_locals + 1),
+                _ => ("goto", function.num_locals),
+            };
+            let extra = depth - expected as isize;
+            if extra > 0 {
+                let values = if extra == 1 { "value" } else { "values" };
+                let message = format!(
+                    "The stack has {} more {} than expected at this \"{}\", so something pushed \
+                    might never be used.",
+                    extra, values, command
+                );
+                let mut warning = error_at(program, function.start + index, options, message);
+                warning.severity = Severity::Warning;
+                warnings.push(warning);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, test_util::parse_program};
+
+    fn validate_error(source: &str, options: &Options) -> CompileError {
+        let program = parse_program(source, options);
+        let err = validate(&program, options).unwrap_err();
+        *err.downcast::<CompileError>().unwrap()
+    }
+
+    #[test]
+    fn warns_about_a_function_nothing_calls_first() {
+        let options = Options::default();
+        let program = parse_program("function Foo 1\npush local 0\nreturn\n", &options);
+        let warnings = lint(&program, &options);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].severity, Severity::Warning);
+        assert!(warnings[0].message.contains("\"Foo\""));
+        let location = warnings[0].location.as_ref().unwrap();
+        assert_eq!(
+            (&location.file[..], location.line, location.col),
+            ("Test.vm", 1, 1)
+        );
+    }
+
+    #[test]
+    fn labels_and_functions_share_names() {
+        let source = "label Foo\nfunction Foo 0\npush constant 0\nreturn\n";
+        let err = validate_error(source, &Options::default());
+        assert_eq!(
+            err.message,
+            "The name \"Foo\" is defined as both a label and a function (previously defined at \
+            Test.vm:1:1)."
+        );
+        assert_eq!(err.location.unwrap().line, 2);
+    }
+
+    #[test]
+    fn merges_a_label_defined_twice_in_a_row() {
+        let options = Options::default();
+        let source = "label L\nlabel L\ngoto L\n";
+        let mut program = parse_program(source, &options);
+        let warnings = merge_adjacent_labels(&mut program, &options);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].severity, Severity::Warning);
+        assert!(warnings[0].message.contains("defined twice in a row"));
+        assert_eq!(warnings[0].location.as_ref().unwrap().line, 2);
+        assert_eq!(
+            program.commands,
+            vec![
+                VmCommand::Label("L".to_owned()),
+                VmCommand::Goto("L".to_owned())
+            ]
+        );
+        assert!(validate(&program, &options).is_ok());
+        // Anywhere else it is still a duplicate.
+        let err = validate_error("label L\npush constant 1\nlabel L\n", &options);
+        assert!(err.message.contains("is defined as a label twice"));
+    }
+
+    #[test]
+    fn warns_about_an_empty_loop_unless_it_halts() {
+        let options = Options::default();
+        let source = "label WAIT\ngoto WAIT\npush constant 1\nlabel END\ngoto END\n";
+        let warnings = lint(&parse_program(source, &options), &options);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].message.starts_with("\"goto WAIT\" jumps back"));
+        assert_eq!(warnings[0].location.as_ref().unwrap().line, 2);
+    }
+
+    #[test]
+    fn names_differing_by_case_only_conflict_when_ignoring_case() {
+        let source =
+            "function Foo 0\npush constant 0\nreturn\nfunction foo 0\npush constant 1\nreturn\n";
+        assert!(validate(
+            &parse_program(source, &Options::default()),
+            &Options::default()
+        )
+        .is_ok());
+        let options = Options {
+            case_insensitive: true,
+            ..Options::default()
+        };
+        let err = validate_error(source, &options);
+        assert_eq!(
+            err.message,
+            "Ignoring case, the name \"foo\" (also written \"Foo\") is defined as a function \
+            twice (previously defined at Test.vm:1:1)."
+        );
+        assert_eq!(err.location.unwrap().line, 4);
+    }
+
+    #[test]
+    fn warns_about_a_value_nothing_uses_only_when_asked() {
+        let source = "function Main.main 1\npush constant 5\npush constant 1\nreturn\n\
+            function Main.ok 1\npush local 0\nreturn\n";
+        let unused = |options: &Options| {
+            let warnings = lint(&parse_program(source, options), options);
+            let warnings = warnings.into_iter();
+            let warnings = warnings.filter(|warning| warning.message.contains("than expected"));
+            warnings.collect::<Vec<_>>()
+        };
+        assert!(unused(&Options::default()).is_empty());
+        let options = Options {
+            warn_unused_values: true,
+            ..Options::default()
+        };
+        let warnings = unused(&options);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].message,
+            "The stack has 1 more value than expected at this \"return\", so something pushed \
+            might never be used."
+        );
+        assert_eq!(warnings[0].location.as_ref().unwrap().line, 4);
+    }
+
+    #[test]
+    fn warns_about_this_without_setting_pointer_0() {
+        let options = Options::default();
+        let source = "function Main.get 0\npush constant 1\npush this 2\nadd\nreturn\n\
+            function Main.set 0\npush argument 0\npop pointer 0\npush this 2\nreturn\n";
+        let warnings = lint(&parse_program(source, &options), &options);
+        let warnings: Vec<_> = warnings
+            .iter()
+            .filter(|warning| warning.message.contains("without ever setting it"))
+            .collect();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].message,
+            "The function \"Main.get\" uses \"this\" without ever setting it with \"pop pointer \
+            0\", so it will access whatever its caller left there."
+        );
+        assert_eq!(warnings[0].location.as_ref().unwrap().line, 3);
+    }
+
+    #[test]
+    fn warns_about_pointing_past_the_heap() {
+        let options = Options::default();
+        let source = "push constant 20000\npop pointer 0\npush constant 2048\npop pointer 1\n";
+        let warnings = lint(&parse_program(source, &options), &options);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].message,
+            "\"pop pointer 0\" sets THIS to 20000, which is past the end of the heap at 16383."
+        );
+        assert_eq!(warnings[0].location.as_ref().unwrap().line, 2);
+    }
+
+    #[test]
+    fn warns_about_returning_nothing() {
+        let options = Options::default();
+        let source = "function Main.run 2\npush local 0\npop local 1\nreturn\n\
+            function Main.done 0\npush constant 0\nreturn\n";
+        let warnings = lint(&parse_program(source, &options), &options);
+        let warnings: Vec<_> = warnings
+            .iter()
+            .filter(|warning| warning.message.contains("no value on the stack"))
+            .collect();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].message.ends_with(
+            "add \"push constant 0\" before it if the function doesn't return anything."
+        ));
+        assert_eq!(warnings[0].location.as_ref().unwrap().line, 4);
+    }
+
+    #[test]
+    fn warns_about_calls_with_different_numbers_of_arguments() {
+        let options = Options::default();
+        let arity_warnings = |source: &str| -> Vec<String> {
+            let warnings = lint(&parse_program(source, &options), &options);
+            warnings
+                .into_iter()
+                .map(|warning| warning.message)
+                .filter(|message| message.contains("but the call"))
+                .collect()
+        };
+        let consistent = "push constant 1\npush constant 2\ncall Foo 2\n\
+            push constant 3\npush constant 4\ncall Foo 2\n";
+        assert_eq!(arity_warnings(consistent), Vec::<String>::new());
+        let inconsistent = "push constant 1\npush constant 2\ncall Foo 2\n\
+            push constant 3\npush constant 4\npush constant 5\ncall Foo 3\n";
+        assert_eq!(
+            arity_warnings(inconsistent),
+            vec![
+                "This calls \"Foo\" with 3 arguments, but the call at Test.vm:3:1 passes 2."
+                    .to_owned()
+            ]
+        );
+    }
+
+    #[test]
+    fn returns_have_to_be_inside_a_function() {
+        let options = Options::default();
+        let err = validate_error("push constant 1\nreturn\n", &options);
+        assert!(err
+            .message
+            .starts_with("This \"return\" is not inside of any function"));
+        assert_eq!(err.location.unwrap().line, 2);
+        let program = parse_program("function Main.f 0\npush constant 1\nreturn\n", &options);
+        assert!(validate(&program, &options).is_ok());
+        // A function in an earlier file doesn't count.
+        let mut program = program;
+        parse::parse(
+            &mut program,
+            "push constant 1\nreturn\n",
+            "Other.vm",
+            &options,
+        )
+        .unwrap();
+        let err = validate(&program, &options).unwrap_err();
+        let err = err.downcast::<CompileError>().unwrap();
+        assert_eq!(err.location.unwrap().file, "Other.vm");
+    }
+
+    #[test]
+    fn included_files_are_inside_the_function_including_them() {
+        let dir = std::env::temp_dir().join(format!(
+            "translator-test-{}-include-return",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("snippet.vminc"), "push constant 1\npop temp 0\n").unwrap();
+        std::fs::write(dir.join("nested.vminc"), "// @include snippet.vminc\n").unwrap();
+        let options = Options::default();
+        let main_path = dir.join("Main.vm").to_string_lossy().into_owned();
+        for include in &["snippet.vminc", "nested.vminc"] {
+            let source = format!(
+                "function Main.main 0\n// @include {}\npush constant 0\nreturn\n",
+                include
+            );
+            let mut program = VmProgram::new();
+            parse::parse(&mut program, &source, &main_path, &options).unwrap();
+            assert!(program.info[2].included_in.is_some());
+            validate(&program, &options).unwrap();
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
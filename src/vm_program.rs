@@ -1,9 +1,21 @@
+use crate::{
+    error::{CompileError, Location},
+    json, layout,
+    translate::MAX_UNROLLED_LOCALS,
+};
+use std::{
+    collections::BTreeSet,
+    convert::TryFrom,
+    fmt::{Display, Formatter},
+};
+
 /// Creates an enum with a public function `from_name` that returns the corresponding enum variant
-/// given a matching string.
+/// given a matching string, and a `TryFrom<&str>` that does the same with an error listing every
+/// name it accepts. Extra variants give the names they accept in brackets before their match arm.
 macro_rules! keyword_enum {
     ($EnumName:ident {
         $($EnumVariantName:ident $name_in_source:literal),*$(,)?
-        $(($($extra_variant:tt)*) $($ev_match_arm:tt)*),*
+        $(($($extra_variant:tt)*) [$extra_names:expr] $($ev_match_arm:tt)*),*
     }) => {
         #[derive(Clone, Copy, PartialEq, Eq, Debug)]
         pub enum $EnumName {
@@ -12,6 +24,8 @@ macro_rules! keyword_enum {
         }
         impl $EnumName {
             pub fn from_name(name: &str) -> Option<Self> {
+                // The extra arms can contain a catch-all pattern.
+                #[allow(unreachable_patterns)]
                 match name {
                     $($name_in_source => Some(Self::$EnumVariantName),)*
                     $($($ev_match_arm)*,)*
@@ -22,6 +36,33 @@ macro_rules! keyword_enum {
             pub fn all_names() -> &'static [&'static str] {
                 &[$($name_in_source,)*]
             }
+
+            /// Every name `from_name` accepts, including the ones for extra variants.
+            pub fn expected_names() -> Vec<&'static str> {
+                #[allow(unused_mut)]
+                let mut names = Self::all_names().to_vec();
+                $(names.extend_from_slice($extra_names);)*
+                names
+            }
+
+            /// The keyword for this variant, if it has one of its own.
+            pub fn name(self) -> Option<&'static str> {
+                let mut names = Self::all_names().iter().copied();
+                names.find(|name| Self::from_name(name) == Some(self))
+            }
+        }
+        impl TryFrom<&str> for $EnumName {
+            type Error = CompileError;
+
+            fn try_from(name: &str) -> Result<Self, Self::Error> {
+                Self::from_name(name).ok_or_else(|| {
+                    let expected = Self::expected_names().join(", ");
+                    CompileError::new(format!(
+                        "Found unknown symbol \"{}\", expected one of:\n{}.",
+                        name, expected
+                    ))
+                })
+            }
         }
     }
 }
@@ -51,6 +92,41 @@ keyword_enum!(MemorySegment {
     Temp "temp",
 });
 
+/// How the address of a value in a memory segment is determined.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AddressingMode {
+    /// The segment starts wherever a pointer (LCL, ARG, THIS, or THAT) points to.
+    Indirect,
+    /// The segment is always at the same place in memory.
+    Fixed,
+    /// There is no memory involved, the index is the value.
+    Immediate,
+}
+
+impl MemorySegment {
+    pub fn addressing_mode(self) -> AddressingMode {
+        use MemorySegment::*;
+        match self {
+            Local | Argument | This | That => AddressingMode::Indirect,
+            Static | Pointer | Temp => AddressingMode::Fixed,
+            Constant => AddressingMode::Immediate,
+        }
+    }
+
+    /// The symbol for the pointer to the start of the segment, only for segments with indirect
+    /// addressing.
+    pub fn pointer_symbol(self) -> Option<&'static str> {
+        use MemorySegment::*;
+        match self {
+            Local => Some("LCL"),
+            Argument => Some("ARG"),
+            This => Some("THIS"),
+            That => Some("THAT"),
+            Static | Pointer | Temp | Constant => None,
+        }
+    }
+}
+
 // Just different keywords used to identify non-arithmetic commands.
 keyword_enum!(CommandName {
     Push "push",
@@ -61,10 +137,72 @@ keyword_enum!(CommandName {
     Function "function",
     Return "return",
     Call "call",
-    (Arithmetic(ArithmeticOpcode))
-        name => ArithmeticOpcode::from_name(name).map(|o| Self::Arithmetic(o))
+    Nop "nop",
+    (Arithmetic(ArithmeticOpcode)) [ArithmeticOpcode::all_names()]
+        name => ArithmeticOpcode::from_name(name).map(Self::Arithmetic)
 });
 
+/// Whether a character can appear in an identifier. Identifiers also cannot start with a digit.
+pub fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == ':'
+}
+
+pub fn is_legal_identifier(name: &str) -> bool {
+    let starts_with_digit = name.starts_with(|c: char| c.is_ascii_digit());
+    !name.is_empty() && !starts_with_digit && name.chars().all(is_identifier_char)
+}
+
+/// Turns an arbitrary name (from a quoted identifier) into a legal one. Every character that isn't
+/// allowed is replaced by its code point in hex surrounded by dollar signs, so `Foo Bar` becomes
+/// `Foo$20$Bar`. Since `$` can't appear in a normal identifier, this never collides with one and
+/// can be undone with `demangle_identifier`.
+pub fn mangle_identifier(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    for (idx, c) in name.chars().enumerate() {
+        if is_identifier_char(c) && !(idx == 0 && c.is_ascii_digit()) {
+            result.push(c);
+        } else {
+            result.push_str(&format!("${:x}$", c as u32));
+        }
+    }
+    result
+}
+
+/// Turns an arbitrary name into a legal identifier by replacing every character that isn't allowed
+/// with `_`, for suggesting a fix. Unlike `mangle_identifier` this can't be undone, and different
+/// names can end up the same.
+pub fn sanitize_identifier(name: &str) -> String {
+    let mut result: String = name
+        .chars()
+        .map(|c| if is_identifier_char(c) { c } else { '_' })
+        .collect();
+    if result.is_empty() || result.starts_with(|c: char| c.is_ascii_digit()) {
+        result.insert(0, '_');
+    }
+    result
+}
+
+/// Recovers the original name of a mangled identifier, or returns None if it was not mangled.
+pub fn demangle_identifier(name: &str) -> Option<String> {
+    if !name.contains('$') {
+        return None;
+    }
+    let mut result = String::with_capacity(name.len());
+    let mut pieces = name.split('$');
+    // Pieces alternate between normal text and escaped characters.
+    while let Some(text) = pieces.next() {
+        result.push_str(text);
+        if let Some(code) = pieces.next() {
+            result.push(
+                u32::from_str_radix(code, 16)
+                    .ok()
+                    .and_then(char::from_u32)?,
+            );
+        }
+    }
+    Some(result)
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum VmCommand {
     Arithmetic(ArithmeticOpcode),
@@ -72,32 +210,620 @@ pub enum VmCommand {
     Push(MemorySegment, usize),
     Pop(MemorySegment, usize),
     Label(String),
-    FnSetup { num_locals: usize },
-    Call { fn_name: String, num_args: usize },
+    FnSetup {
+        num_locals: usize,
+    },
+    Call {
+        fn_name: String,
+        num_args: usize,
+    },
     Goto(String),
     IfGoto(String),
     Return,
+    /// Does nothing, only used as a marker by tools that generate VM code.
+    Nop,
+    /// A line of Hack assembly to put in the output as it is.
+    RawAsm(String),
+}
+
+impl VmCommand {
+    /// Whether execution never continues on to the next command.
+    pub fn is_terminator(&self) -> bool {
+        matches!(self, Self::Goto(_) | Self::Return)
+    }
+
+    /// Whether the next command starts a new basic block because this one might jump somewhere
+    /// else. Raw assembly counts since there is no telling what it does. Labels also start new
+    /// blocks, but that is because of the label itself rather than the command before it.
+    pub fn is_block_leader_after(&self) -> bool {
+        matches!(
+            self,
+            Self::Goto(_) | Self::IfGoto(_) | Self::Return | Self::RawAsm(_)
+        )
+    }
+
+    /// Roughly how many instructions the command will be translated into.
+    pub fn estimated_instruction_count(&self) -> usize {
+        use ArithmeticOpcode::*;
+        match self {
+            Self::Arithmetic(Neg) | Self::Arithmetic(Not) => 3,
+            Self::Arithmetic(Eq) | Self::Arithmetic(Gt) | Self::Arithmetic(Lt) => 18,
+            Self::Arithmetic(_) => 12,
+            Self::Push(segment, _) if segment.addressing_mode() == AddressingMode::Indirect => 11,
+            Self::Push(segment, _) if segment.addressing_mode() == AddressingMode::Fixed => 6,
+            Self::Push(..) => 8,
+            Self::Pop(segment, _) if segment.addressing_mode() == AddressingMode::Indirect => 22,
+            Self::Pop(..) => 5,
+            Self::Label(_) | Self::Nop => 0,
+            Self::RawAsm(_) => 1,
+            // Each local is a push of 0, unless there are enough of them to push in a loop.
+            Self::FnSetup { num_locals } if *num_locals > MAX_UNROLLED_LOCALS => 13,
+            Self::FnSetup { num_locals } => num_locals * 7,
+            Self::Call { .. } => 54,
+            Self::Goto(_) => 2,
+            Self::IfGoto(_) => 11,
+            Self::Return => 89,
+        }
+    }
+}
+
+/// Writes the command the way it would appear in a .vm file, except function setup only has its
+/// number of locals since its name is in the label before it.
+impl Display for VmCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Arithmetic(opcode) => write!(f, "{}", opcode.name().unwrap()),
+            Self::Push(segment, index) => write!(f, "push {} {}", segment.name().unwrap(), index),
+            Self::Pop(segment, index) => write!(f, "pop {} {}", segment.name().unwrap(), index),
+            Self::Label(label) => write!(f, "label {}", label),
+            Self::FnSetup { num_locals } => write!(f, "function {}", num_locals),
+            Self::Call { fn_name, num_args } => write!(f, "call {} {}", fn_name, num_args),
+            Self::Goto(label) => write!(f, "goto {}", label),
+            Self::IfGoto(label) => write!(f, "if-goto {}", label),
+            Self::Return => write!(f, "return"),
+            Self::Nop => write!(f, "nop"),
+            Self::RawAsm(code) => write!(f, "! {}", code),
+        }
+    }
+}
+
+/// How to write a name in VM code so that it parses back into the same name. Names that were
+/// quoted are quoted again.
+fn vm_identifier(name: &str) -> String {
+    match demangle_identifier(name) {
+        Some(original) => format!(
+            "\"{}\"",
+            original.replace('\\', "\\\\").replace('"', "\\\"")
+        ),
+        None => name.to_owned(),
+    }
+}
+
+/// Where a command was written. Lines and columns start at 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourcePosition {
+    /// Index into `VmProgram::files`.
+    pub file: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+/// Extra information about a command that doesn't affect what it does.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CommandInfo {
+    /// Where the command came from, if it was parsed from a file.
+    pub position: Option<SourcePosition>,
+    /// Where the code that generated this command came from, as given by a `// @src ...`
+    /// annotation before it. This is copied into the output as a comment.
+    pub source_annotation: Option<String>,
+    /// Names for the locals of a function, as given by a `// @locals ...` annotation before it.
+    /// Only used for comments, and only on the setup for a function.
+    pub local_names: Option<Vec<String>>,
+    /// For commands from a file pulled in with `// @include`, the index into `VmProgram::files` of
+    /// the file that the includes started from, which wasn't included by anything itself.
+    pub included_in: Option<usize>,
+}
+
+/// Builds a program by hand, for library users that generate commands themselves instead of
+/// parsing them.
+#[derive(Debug, Default)]
+pub struct ProgramBuilder {
+    program: VmProgram,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn command(self, command: VmCommand) -> Self {
+        self.commands(std::iter::once(command))
+    }
+
+    pub fn commands(mut self, commands: impl IntoIterator<Item = VmCommand>) -> Self {
+        self.program.append_commands(commands);
+        self
+    }
+
+    /// Adds a function along with its body.
+    pub fn function(
+        self,
+        name: &str,
+        num_locals: usize,
+        body: impl IntoIterator<Item = VmCommand>,
+    ) -> Self {
+        self.command(VmCommand::Label(name.to_owned()))
+            .command(VmCommand::FnSetup { num_locals })
+            .commands(body)
+    }
+
+    pub fn build(self) -> VmProgram {
+        self.program
+    }
+}
+
+/// A function and the commands in it, split out of a `VmProgram`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Function {
+    /// None for the commands before the first function, which run at the top level.
+    pub name: Option<String>,
+    pub num_locals: usize,
+    /// Every command after the function's setup, up to the start of the next function.
+    pub body: Vec<VmCommand>,
+    /// The index in `VmProgram::commands` of the first command in `body`.
+    pub start: usize,
+    /// Where the function was defined.
+    pub position: Option<SourcePosition>,
 }
 
-#[derive(Debug)]
+impl Function {
+    /// Formats the name and number of locals of the function along with where it was defined as
+    /// a single-line JSON object, for tools that want to know what a project defines.
+    pub fn to_json(&self, location: Option<&Location>) -> String {
+        let (file, line, col) = match location {
+            Some(location) => (
+                json::string(&location.file),
+                location.line.to_string(),
+                location.col.to_string(),
+            ),
+            None => ("null".to_owned(), "null".to_owned(), "null".to_owned()),
+        };
+        let name = match &self.name {
+            Some(name) => json::string(name),
+            None => "null".to_owned(),
+        };
+        format!(
+            "{{\"name\":{},\"num_locals\":{},\"file\":{},\"line\":{},\"col\":{}}}",
+            name, self.num_locals, file, line, col
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct VmProgram {
     pub commands: Vec<VmCommand>,
+    /// Information about each command. Always as long as `commands`.
+    pub info: Vec<CommandInfo>,
+    /// The paths of every file that was parsed into this program.
+    pub files: Vec<String>,
+    /// Where the static variables of each file in `files` start.
+    pub file_static_bases: Vec<usize>,
     pub static_size: usize,
+    /// The name and index of every shared static variable. They are stored from the end of static
+    /// memory downwards, so the first one is in the last slot.
+    pub shared_statics: Vec<(String, usize)>,
 }
 
 impl VmProgram {
     pub fn new() -> Self {
-        Self {
-            commands: Vec::new(),
-            static_size: 0,
-        }
+        Self::default()
     }
 
     pub fn push_command(&mut self, command: VmCommand) {
+        self.push_command_with_info(command, CommandInfo::default());
+    }
+
+    pub fn push_command_with_info(&mut self, command: VmCommand, info: CommandInfo) {
         self.commands.push(command);
+        self.info.push(info);
+    }
+
+    /// Adds commands that weren't parsed from a file, making room in the static segment for any
+    /// static variables they use. Their indexes are used as they are, the same as the ones the
+    /// parser makes for static variables.
+    pub fn append_commands(&mut self, commands: impl IntoIterator<Item = VmCommand>) {
+        for command in commands {
+            if let VmCommand::Push(MemorySegment::Static, index)
+            | VmCommand::Pop(MemorySegment::Static, index) = command
+            {
+                let first_shared = layout::STATIC_MEMORY_SIZE - self.shared_statics.len();
+                if index < first_shared {
+                    self.increase_static_size(index + 1);
+                }
+            }
+            self.push_command(command);
+        }
+    }
+
+    /// Returns the index new commands from this file should use to refer to it. The file's static
+    /// variables go after those of every file added before it.
+    pub fn add_file(&mut self, path: &str) -> usize {
+        self.files.push(path.to_owned());
+        self.file_static_bases.push(self.static_size);
+        self.files.len() - 1
+    }
+
+    /// Where the command at the given index came from, if it was parsed from a file.
+    pub fn location(&self, index: usize) -> Option<Location> {
+        let position = self.info[index].position.as_ref()?;
+        Some(Location {
+            file: self.files[position.file].clone(),
+            line: position.line,
+            col: position.col,
+        })
+    }
+
+    /// The index in the static segment a shared static variable is stored at, giving it a slot if
+    /// it doesn't have one yet.
+    pub fn shared_static_slot(&mut self, name: &str, index: usize) -> usize {
+        let existing = self
+            .shared_statics
+            .iter()
+            .position(|(other_name, other_index)| other_name == name && *other_index == index);
+        let position = existing.unwrap_or_else(|| {
+            self.shared_statics.push((name.to_owned(), index));
+            self.shared_statics.len() - 1
+        });
+        layout::STATIC_MEMORY_SIZE - 1 - position
+    }
+
+    /// Roughly how many instructions the program will be translated into, not counting the
+    /// bootstrap code.
+    pub fn estimated_instruction_count(&self) -> usize {
+        let commands = self.commands.iter();
+        commands.map(VmCommand::estimated_instruction_count).sum()
+    }
+
+    /// How many static slots are used, including shared ones.
+    pub fn static_slots_used(&self) -> usize {
+        self.static_size + self.shared_statics.len()
+    }
+
+    /// Splits the program up into its functions, in order. Commands before the first function are
+    /// put in one with no name, which is left out if there aren't any.
+    pub fn functions(&self) -> Vec<Function> {
+        let mut functions = Vec::new();
+        let mut current = Function {
+            name: None,
+            num_locals: 0,
+            body: Vec::new(),
+            start: 0,
+            position: None,
+        };
+        let mut index = 0;
+        while index < self.commands.len() {
+            match (&self.commands[index], self.commands.get(index + 1)) {
+                (VmCommand::Label(name), Some(VmCommand::FnSetup { num_locals })) => {
+                    let next = Function {
+                        name: Some(name.clone()),
+                        num_locals: *num_locals,
+                        body: Vec::new(),
+                        start: index + 2,
+                        position: self.info[index].position.clone(),
+                    };
+                    let previous = std::mem::replace(&mut current, next);
+                    if previous.name.is_some() || !previous.body.is_empty() {
+                        functions.push(previous);
+                    }
+                    index += 2;
+                }
+                (command, _) => {
+                    current.body.push(command.clone());
+                    index += 1;
+                }
+            }
+        }
+        if current.name.is_some() || !current.body.is_empty() {
+            functions.push(current);
+        }
+        functions
+    }
+
+    /// The names of every function that is called but not defined in the program, like the OS
+    /// functions a program depends on when the OS isn't included.
+    pub fn external_functions(&self) -> BTreeSet<String> {
+        let mut defined = BTreeSet::new();
+        let mut called = BTreeSet::new();
+        for (index, command) in self.commands.iter().enumerate() {
+            match (command, self.commands.get(index + 1)) {
+                (VmCommand::Label(name), Some(VmCommand::FnSetup { .. })) => {
+                    defined.insert(name);
+                }
+                (VmCommand::Call { fn_name, .. }, _) => {
+                    called.insert(fn_name);
+                }
+                _ => (),
+            }
+        }
+        let external = called.difference(&defined);
+        external.map(|name| (*name).clone()).collect()
+    }
+
+    /// Writes the program back out as VM code, one command per line with a blank line before each
+    /// function, for `--emit-vm-normalized`. Parsing the result as a single file gives back the
+    /// same commands. Static variables keep the indexes they were given across the whole program,
+    /// and annotations are written out again before the commands they belong to.
+    pub fn to_vm_source(&self) -> String {
+        let first_shared = layout::STATIC_MEMORY_SIZE - self.shared_statics.len();
+        let static_variable = |index: usize| {
+            if index < first_shared {
+                format!("static {}", index)
+            } else {
+                let (name, index) = &self.shared_statics[layout::STATIC_MEMORY_SIZE - 1 - index];
+                format!("shared {} {}", vm_identifier(name), index)
+            }
+        };
+        let mut result = String::new();
+        let mut index = 0;
+        while index < self.commands.len() {
+            let info = &self.info[index];
+            let command = &self.commands[index];
+            let next = self.commands.get(index + 1);
+            if let (VmCommand::Label(_), Some(VmCommand::FnSetup { .. }), false) =
+                (command, next, result.is_empty())
+            {
+                result.push('\n');
+            }
+            if let Some(source) = &info.source_annotation {
+                result.push_str(&format!("// @src {}\n", source));
+            }
+            let line = match (command, next) {
+                (VmCommand::Label(name), Some(VmCommand::FnSetup { num_locals })) => {
+                    if let Some(names) = &self.info[index + 1].local_names {
+                        result.push_str(&format!("// @locals {}\n", names.join(" ")));
+                    }
+                    index += 1;
+                    format!("function {} {}", vm_identifier(name), num_locals)
+                }
+                (VmCommand::Push(MemorySegment::Static, index), _) => {
+                    format!("push {}", static_variable(*index))
+                }
+                (VmCommand::Pop(MemorySegment::Static, index), _) => {
+                    format!("pop {}", static_variable(*index))
+                }
+                (VmCommand::Label(label), _) => format!("label {}", vm_identifier(label)),
+                (VmCommand::Goto(label), _) => format!("goto {}", vm_identifier(label)),
+                (VmCommand::IfGoto(label), _) => format!("if-goto {}", vm_identifier(label)),
+                (VmCommand::Call { fn_name, num_args }, _) => {
+                    format!("call {} {}", vm_identifier(fn_name), num_args)
+                }
+                (command, _) => command.to_string(),
+            };
+            result.push_str(&line);
+            result.push('\n');
+            index += 1;
+        }
+        result
     }
 
     pub fn increase_static_size(&mut self, required_capacity: usize) {
         self.static_size = self.static_size.max(required_capacity);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, options::Options, test_util::parse_program, translate};
+
+    #[test]
+    fn every_segment_has_an_addressing_mode() {
+        use AddressingMode::*;
+        let modes: Vec<_> = MemorySegment::all_names()
+            .iter()
+            .map(|name| {
+                let segment = MemorySegment::from_name(name).unwrap();
+                (*name, segment.addressing_mode())
+            })
+            .collect();
+        assert_eq!(
+            modes,
+            vec![
+                ("argument", Indirect),
+                ("local", Indirect),
+                ("static", Fixed),
+                ("constant", Immediate),
+                ("this", Indirect),
+                ("that", Indirect),
+                ("pointer", Fixed),
+                ("temp", Fixed),
+            ]
+        );
+    }
+
+    #[test]
+    fn instruction_estimate_is_close() {
+        let options = Options::default();
+        let source = "function Main.main 2\npush argument 0\npush constant 3\nlt\n\
+            if-goto END\npush local 1\npop that 2\ncall Main.main 1\nlabel END\n\
+            push static 0\nreturn\n";
+        let program = parse_program(source, &options);
+        let estimate = program.estimated_instruction_count();
+        let result = translate::translate(program, &options).unwrap();
+        let actual = assemble::count_instructions(&result);
+        assert!(
+            estimate * 2 > actual && estimate < actual * 2,
+            "estimated {} instructions, but there are {}",
+            estimate,
+            actual
+        );
+    }
+
+    #[test]
+    fn only_goto_and_return_are_terminators() {
+        let name = || "L".to_owned();
+        let commands = [
+            (VmCommand::Arithmetic(ArithmeticOpcode::Add), false, false),
+            (VmCommand::Push(MemorySegment::Local, 0), false, false),
+            (VmCommand::Pop(MemorySegment::Local, 0), false, false),
+            (VmCommand::Label(name()), false, false),
+            (VmCommand::FnSetup { num_locals: 1 }, false, false),
+            (
+                VmCommand::Call {
+                    fn_name: name(),
+                    num_args: 0,
+                },
+                false,
+                false,
+            ),
+            (VmCommand::Goto(name()), true, true),
+            (VmCommand::IfGoto(name()), false, true),
+            (VmCommand::Return, true, true),
+            (VmCommand::Nop, false, false),
+            (VmCommand::RawAsm("@SP".to_owned()), false, true),
+        ];
+        for (command, terminator, leader_after) in &commands {
+            assert_eq!(command.is_terminator(), *terminator, "{}", command);
+            assert_eq!(
+                command.is_block_leader_after(),
+                *leader_after,
+                "{}",
+                command
+            );
+        }
+    }
+
+    #[test]
+    fn functions_called_but_not_defined_are_external() {
+        let source = "function Main.main 0\npush constant 16\ncall Math.sqrt 1\n\
+            call Main.helper 0\nreturn\nfunction Main.helper 0\npush constant 0\nreturn\n";
+        let program = parse_program(source, &Options::default());
+        let external: Vec<_> = program.external_functions().into_iter().collect();
+        assert_eq!(external, vec!["Math.sqrt".to_owned()]);
+    }
+
+    #[test]
+    fn splits_the_program_into_functions() {
+        let source = "function Main.main 1\npush constant 1\nreturn\n\
+            function Main.other 2\npush local 1\npop local 0\nreturn\n";
+        let functions = parse_program(source, &Options::default()).functions();
+        let summary: Vec<_> = functions
+            .iter()
+            .map(|function| {
+                (
+                    function.name.as_deref(),
+                    function.num_locals,
+                    function.start,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![(Some("Main.main"), 1, 2), (Some("Main.other"), 2, 6)]
+        );
+        assert_eq!(
+            functions[0].body,
+            vec![
+                VmCommand::Push(MemorySegment::Constant, 1),
+                VmCommand::Return
+            ]
+        );
+        assert_eq!(
+            functions[1].body,
+            vec![
+                VmCommand::Push(MemorySegment::Local, 1),
+                VmCommand::Pop(MemorySegment::Local, 0),
+                VmCommand::Return
+            ]
+        );
+        assert_eq!(functions[1].position.as_ref().unwrap().line, 4);
+    }
+
+    #[test]
+    fn builder_makes_room_for_statics() {
+        let program = ProgramBuilder::new()
+            .function(
+                "Main.main",
+                0,
+                vec![
+                    VmCommand::Push(MemorySegment::Constant, 7),
+                    VmCommand::Pop(MemorySegment::Static, 2),
+                    VmCommand::Push(MemorySegment::Static, 2),
+                    VmCommand::Return,
+                ],
+            )
+            .build();
+        assert_eq!(program.static_size, 3);
+        assert_eq!(program.static_slots_used(), 3);
+        assert_eq!(program.functions().len(), 1);
+        let result = translate::translate(program, &Options::default()).unwrap();
+        assert!(result.contains("(Main.main)\n"));
+        assert!(result.contains("@18\n"));
+    }
+
+    #[test]
+    fn normalizing_is_canonical_and_idempotent() {
+        let options = Options::default();
+        let messy = "function   Main.main 1 // comment\n\tpush constant 7 pop local 0\n\n\n\
+            push local 0\r\nif-goto   END\n  call Main.main 0\nlabel END\nreturn";
+        let normalized = parse_program(messy, &options).to_vm_source();
+        assert_eq!(
+            normalized,
+            "function Main.main 1\npush constant 7\npop local 0\npush local 0\nif-goto END\n\
+            call Main.main 0\nlabel END\nreturn\n"
+        );
+        assert_eq!(
+            parse_program(&normalized, &options).to_vm_source(),
+            normalized
+        );
+    }
+
+    #[test]
+    fn keywords_convert_from_strings() {
+        use std::convert::TryInto;
+        let opcode: Result<ArithmeticOpcode, _> = "add".try_into();
+        assert_eq!(opcode.unwrap(), ArithmeticOpcode::Add);
+        let command: Result<CommandName, _> = "lt".try_into();
+        assert_eq!(
+            command.unwrap(),
+            CommandName::Arithmetic(ArithmeticOpcode::Lt)
+        );
+        let err = MemorySegment::try_from("stack").unwrap_err();
+        assert_eq!(
+            err.message,
+            "Found unknown symbol \"stack\", expected one of:\n\
+            argument, local, static, constant, this, that, pointer, temp."
+        );
+    }
+
+    #[test]
+    fn clones_are_independent() {
+        let mut program = VmProgram::new();
+        program.push_command(VmCommand::Push(MemorySegment::Static, 0));
+        program.static_size = 1;
+        let original = program.clone();
+        program.push_command(VmCommand::Return);
+        program.static_size = 5;
+        assert_eq!(
+            original.commands,
+            vec![VmCommand::Push(MemorySegment::Static, 0)]
+        );
+        assert_eq!(original.static_size, 1);
+        assert_eq!(program.commands.len(), 2);
+        assert_eq!(VmProgram::default().commands, VmProgram::new().commands);
+    }
+
+    #[test]
+    fn function_setup_estimates_are_exact() {
+        for num_locals in 0..=MAX_UNROLLED_LOCALS + 3 {
+            let command = VmCommand::FnSetup { num_locals };
+            let code = translate::expand_command(&command).unwrap();
+            assert_eq!(
+                command.estimated_instruction_count(),
+                assemble::count_instructions(&code),
+                "{} locals",
+                num_locals
+            );
+        }
+    }
+}